@@ -1,216 +1,3258 @@
 //! BundleAccess capability server with triple-guard protection.
 
+use crate::audit::{record_audit, AuditEvent, AuditSampler, AuditSink};
+use crate::auth::Authenticator;
 use crate::bundle_capnp;
+use crate::contents::BundleHandle;
+use crate::error::MembraneError;
+use crate::latency::LatencyTracker;
+use crate::pubkey::keccak256;
+use crate::registry::{InFlightPermit, InFlightTracker};
 use crate::revocation::RevocationGuard;
 use capnp::capability::Promise;
 use capnp::Error;
 use capnp_rpc::pry;
-use membrane_core::EpochGuard;
-use std::sync::Arc;
+use membrane_core::{EpochChainMap, EpochGuard};
+use std::cell::Cell;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 /// Guard that checks whether a target block is within the grant's validity window.
+///
+/// The window is usually one inclusive range, but may be several disjoint
+/// ones (see [`BlockWindowGuard::multi`]). `valid_from`/`valid_until`
+/// always span every range.
 #[derive(Clone, Debug)]
 pub struct BlockWindowGuard {
     pub valid_from: u64,
     pub valid_until: u64,
+    /// Disjoint inclusive ranges; when set, only blocks inside one of them
+    /// pass.
+    pub ranges: Option<Vec<(u64, u64)>>,
 }
 
 impl BlockWindowGuard {
+    /// One contiguous window `[valid_from, valid_until]`.
+    pub fn single(valid_from: u64, valid_until: u64) -> Self {
+        Self {
+            valid_from,
+            valid_until,
+            ranges: None,
+        }
+    }
+
+    /// Several inclusive windows, e.g. only the slots a searcher expects to
+    /// win.
+    pub fn multi(mut ranges: Vec<(u64, u64)>) -> Self {
+        ranges.sort_unstable();
+        Self {
+            valid_from: ranges.first().map_or(0, |r| r.0),
+            valid_until: ranges.iter().map(|r| r.1).max().unwrap_or(0),
+            ranges: Some(ranges),
+        }
+    }
+
+    fn contains(&self, target_block: u64) -> bool {
+        match &self.ranges {
+            Some(ranges) => ranges
+                .iter()
+                .any(|&(from, until)| (from..=until).contains(&target_block)),
+            None => (self.valid_from..=self.valid_until).contains(&target_block),
+        }
+    }
+
     pub fn check(&self, target_block: u64) -> Result<(), Error> {
-        if target_block < self.valid_from || target_block > self.valid_until {
-            return Err(Error::failed(format!(
-                "blockOutOfWindow: target {} not in [{}, {}]",
-                target_block, self.valid_from, self.valid_until
-            )));
+        if !self.contains(target_block) {
+            let windows = match &self.ranges {
+                Some(ranges) => ranges.clone(),
+                None => vec![(self.valid_from, self.valid_until)],
+            };
+            return Err(MembraneError::BlockOutOfWindow {
+                target: target_block,
+                windows,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Nearest block inside the window to `target_block`. Between two
+    /// ranges, ties go to the earlier one.
+    pub fn clamp(&self, target_block: u64) -> u64 {
+        match &self.ranges {
+            Some(ranges) if !ranges.is_empty() => ranges
+                .iter()
+                .map(|&(from, until)| target_block.clamp(from, until))
+                .min_by_key(|&b| (b.abs_diff(target_block), b))
+                .unwrap_or(target_block),
+            _ => target_block.clamp(self.valid_from, self.valid_until),
+        }
+    }
+
+    /// Blocks of validity left after `current_block`, or `None` once the
+    /// window has passed. The last valid block itself reports `Some(0)`.
+    pub fn remaining(&self, current_block: u64) -> Option<u64> {
+        self.valid_until.checked_sub(current_block)
+    }
+
+    /// Whether `current_block` is past the end of the window.
+    pub fn is_expired(&self, current_block: u64) -> bool {
+        current_block > self.valid_until
+    }
+}
+
+/// Wall-clock expiry, for grants meant to last "the next two minutes"
+/// regardless of how blocks advance.
+///
+/// Uses [`Instant`](std::time::Instant), a monotonic clock, so NTP or
+/// manual clock adjustments can't extend validity.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeWindowGuard {
+    pub valid_until: std::time::Instant,
+}
+
+impl TimeWindowGuard {
+    /// Valid for `duration` from now.
+    pub fn valid_for(duration: std::time::Duration) -> Self {
+        Self {
+            valid_until: std::time::Instant::now() + duration,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        self.check_at(std::time::Instant::now())
+    }
+
+    fn check_at(&self, now: std::time::Instant) -> Result<(), Error> {
+        if now > self.valid_until {
+            return Err(MembraneError::GrantExpired.into());
         }
         Ok(())
     }
 }
 
-/// The bundle's raw transactions (held server-side, never exposed to builder).
+/// Per-grant cap on `simulate`/`include` calls, protecting the builder's
+/// node from a runaway client.
+///
+/// Clones share the budget, so one guard limits a grant across all its
+/// sessions and methods.
 #[derive(Clone, Debug)]
-pub struct BundleSpec {
-    pub txs: Vec<Vec<u8>>,
+pub struct CallBudgetGuard {
+    max_calls: u64,
+    remaining: Arc<AtomicU64>,
 }
 
-/// Result of simulating the bundle against a target block.
+impl CallBudgetGuard {
+    pub fn new(max_calls: u64) -> Self {
+        Self {
+            max_calls,
+            remaining: Arc::new(AtomicU64::new(max_calls)),
+        }
+    }
+
+    /// Calls left before the budget is exhausted.
+    pub fn remaining(&self) -> u64 {
+        self.remaining.load(Ordering::Acquire)
+    }
+
+    /// Fail with `callBudgetExhausted` if no calls are left, without
+    /// spending one.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.remaining() == 0 {
+            return Err(self.exhausted());
+        }
+        Ok(())
+    }
+
+    /// Spend one call, failing with `callBudgetExhausted` if none are left.
+    pub fn consume(&self) -> Result<(), Error> {
+        self.consume_n(1)
+    }
+
+    /// Spend `calls` calls at once, or none if fewer are left.
+    pub fn consume_n(&self, calls: u64) -> Result<(), Error> {
+        self.remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                n.checked_sub(calls)
+            })
+            .map(|_| ())
+            .map_err(|_| self.exhausted())
+    }
+
+    /// Give back calls spent by a call that was then refused.
+    fn refund(&self, calls: u64) {
+        self.remaining.fetch_add(calls, Ordering::AcqRel);
+    }
+
+    fn exhausted(&self) -> Error {
+        MembraneError::CallBudgetExhausted {
+            max_calls: self.max_calls,
+        }
+        .into()
+    }
+}
+
+/// Token-bucket rate limit on simulate/include calls, so one builder
+/// can't monopolize the simulation backend.
+///
+/// Clones share the bucket, so every server minted for a grant draws from
+/// the same tokens. Refill follows tokio's clock, which tests can pause
+/// and advance.
 #[derive(Clone, Debug)]
-pub struct SimResult {
-    pub gas_used: u64,
-    pub success: bool,
-    pub state_root: Vec<u8>,
-    pub revert_reason: String,
+pub struct RateLimitGuard {
+    refill_per_sec: f64,
+    burst: u32,
+    bucket: Arc<Mutex<TokenBucket>>,
 }
 
-/// Abstraction over the simulation backend.
-pub trait BundleSimulator: Send + Sync + 'static {
-    fn simulate(
-        &self,
-        bundle: &BundleSpec,
-        target_block: u64,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>;
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: tokio::time::Instant,
 }
 
-/// The capability server that implements BundleAccess.
+impl RateLimitGuard {
+    /// Allow bursts of up to `burst` calls, refilling `refill_per_sec`
+    /// tokens per second. Starts full.
+    pub fn new(refill_per_sec: f64, burst: u32) -> Self {
+        Self {
+            refill_per_sec,
+            burst,
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                tokens: f64::from(burst),
+                refilled_at: tokio::time::Instant::now(),
+            })),
+        }
+    }
+
+    /// Take one token, failing with an overloaded `rateLimited` error if the
+    /// bucket is empty.
+    pub fn check(&self) -> Result<(), Error> {
+        self.check_at(tokio::time::Instant::now())
+    }
+
+    /// Take `tokens` tokens at once, or none if the bucket holds fewer.
+    pub fn take(&self, tokens: u32) -> Result<(), Error> {
+        self.take_at(tokio::time::Instant::now(), tokens)
+    }
+
+    fn check_at(&self, now: tokio::time::Instant) -> Result<(), Error> {
+        self.take_at(now, 1)
+    }
+
+    fn take_at(&self, now: tokio::time::Instant, tokens: u32) -> Result<(), Error> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_sec)
+            .min(f64::from(self.burst));
+        bucket.refilled_at = now;
+        if bucket.tokens < f64::from(tokens) {
+            return Err(MembraneError::RateLimited {
+                refill_per_sec: self.refill_per_sec,
+                burst: self.burst,
+            }
+            .into());
+        }
+        bucket.tokens -= f64::from(tokens);
+        Ok(())
+    }
+}
+
+/// Sees every guard decision a [`BundleAccessServer`] makes, e.g. to feed
+/// metrics without parsing log lines.
+pub trait GuardObserver: Send + Sync {
+    /// `method` is the BundleAccess method being checked; `outcome` is the
+    /// combined result of its guards.
+    fn on_check(&self, method: &str, target_block: u64, outcome: &Result<(), Error>);
+}
+
+/// Observer that ignores every decision.
+pub struct NoopObserver;
+
+impl GuardObserver for NoopObserver {
+    fn on_check(&self, _method: &str, _target_block: u64, _outcome: &Result<(), Error>) {}
+}
+
+/// Observer that reports each decision as a `tracing` event inside a
+/// `guard_check` span: allowed calls at debug level, denied ones at info.
+pub struct TracingObserver;
+
+impl GuardObserver for TracingObserver {
+    fn on_check(&self, method: &str, target_block: u64, outcome: &Result<(), Error>) {
+        let span = tracing::debug_span!("guard_check", method, target_block);
+        let _entered = span.enter();
+        match outcome {
+            Ok(()) => tracing::debug!(allowed = true),
+            Err(e) => tracing::info!(allowed = false, reason = %e.extra),
+        }
+    }
+}
+
+/// Guard enforcing a simulate-then-include workflow: `include` for a block
+/// is only permitted after a successful `simulate` for that same block.
 ///
-/// Every method call checks three guards in sequence:
-/// 1. EpochGuard — is the session epoch still current?
-/// 2. RevocationGuard — has the searcher revoked?
-/// 3. BlockWindowGuard — is the target block in range?
-pub struct BundleAccessServer {
-    pub epoch_guard: EpochGuard,
-    pub revocation_guard: RevocationGuard,
-    pub block_window: BlockWindowGuard,
-    pub bundle: BundleSpec,
-    pub simulator: Arc<dyn BundleSimulator>,
+/// Clones share state, so one guard tracks a grant across all its sessions.
+#[derive(Clone, Debug, Default)]
+pub struct SimulateFirstGuard {
+    simulated: Arc<Mutex<HashSet<u64>>>,
 }
 
-impl BundleAccessServer {
-    /// Check all guards before processing any method call.
-    fn check_all(&self, target_block: u64) -> Result<(), Error> {
-        self.epoch_guard.check()?;
-        self.revocation_guard.check()?;
-        self.block_window.check(target_block)?;
+impl SimulateFirstGuard {
+    /// Record a successful simulation against `target_block`.
+    pub fn record(&self, target_block: u64) {
+        self.simulated.lock().unwrap().insert(target_block);
+    }
+
+    pub fn check(&self, target_block: u64) -> Result<(), Error> {
+        if !self.simulated.lock().unwrap().contains(&target_block) {
+            return Err(MembraneError::SimulateRequiredFirst {
+                block: target_block,
+            }
+            .into());
+        }
         Ok(())
     }
 }
 
-#[allow(refining_impl_trait)]
-impl bundle_capnp::bundle_access::Server for BundleAccessServer {
-    fn simulate(
-        self: capnp::capability::Rc<Self>,
-        params: bundle_capnp::bundle_access::SimulateParams,
-        mut results: bundle_capnp::bundle_access::SimulateResults,
-    ) -> Promise<(), Error> {
-        let target_block = pry!(params.get()).get_target_block();
-        pry!(self.check_all(target_block));
+/// Guard against a bundle being included at more than one block: the first
+/// successful `include` pins the block, and `include` for any other block
+/// then fails with `alreadyIncluded`. Repeating the pinned block succeeds.
+///
+/// Clones share state, so one guard tracks a grant across all its sessions.
+#[derive(Clone, Debug, Default)]
+pub struct InclusionGuard {
+    /// Block the bundle was included at; `0` while not yet included.
+    included_at: Arc<AtomicU64>,
+}
 
-        let bundle = self.bundle.clone();
-        let simulator = self.simulator.clone();
+impl InclusionGuard {
+    /// Block the bundle was included at, if any.
+    pub fn included_at(&self) -> Option<u64> {
+        match self.included_at.load(Ordering::Acquire) {
+            0 => None,
+            block => Some(block),
+        }
+    }
 
-        Promise::from_future(async move {
-            let sim = simulator.simulate(&bundle, target_block).await?;
-            let mut r = results.get().init_result();
-            r.set_gas_used(sim.gas_used);
-            r.set_success(sim.success);
-            r.set_state_root(&sim.state_root);
-            r.set_revert_reason(&sim.revert_reason);
-            Ok(())
-        })
+    /// Whether [`include`](Self::include) would succeed for `target_block`,
+    /// without pinning anything.
+    pub fn check(&self, target_block: u64) -> Result<(), Error> {
+        match self.included_at() {
+            Some(block) if block != target_block => {
+                Err(MembraneError::AlreadyIncluded { block }.into())
+            }
+            _ => Ok(()),
+        }
     }
 
-    fn include(
-        self: capnp::capability::Rc<Self>,
-        params: bundle_capnp::bundle_access::IncludeParams,
-        mut results: bundle_capnp::bundle_access::IncludeResults,
-    ) -> Promise<(), Error> {
-        let target_block = pry!(params.get()).get_target_block();
-        pry!(self.check_all(target_block));
-        results.get().set_included(true);
-        Promise::ok(())
+    /// Pin the inclusion to `target_block`, unless already pinned elsewhere.
+    pub fn include(&self, target_block: u64) -> Result<(), Error> {
+        self.pin(target_block).map(|_| ())
+    }
+
+    /// [`include`](Self::include), reporting whether this call took the pin.
+    fn pin(&self, target_block: u64) -> Result<bool, Error> {
+        match self.included_at.compare_exchange(
+            0,
+            target_block,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => Ok(true),
+            Err(block) if block == target_block => Ok(false),
+            Err(block) => Err(MembraneError::AlreadyIncluded { block }.into()),
+        }
+    }
+
+    /// Undo a pin taken by [`pin`](Self::pin) for an include that failed.
+    fn unpin(&self, target_block: u64) {
+        let _ =
+            self.included_at
+                .compare_exchange(target_block, 0, Ordering::AcqRel, Ordering::Acquire);
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use membrane_core::epoch::Epoch;
-    use tokio::sync::watch;
+/// Reorg resistance for `include`: the current epoch must have been the
+/// head for at least `min_age` blocks, as measured by `chain_map`.
+#[derive(Clone)]
+pub struct EpochAgeGuard {
+    pub min_age: u64,
+    pub chain_map: Arc<dyn EpochChainMap>,
+}
 
-    struct MockSimulator;
+impl EpochAgeGuard {
+    pub fn check(&self, epoch_guard: &EpochGuard) -> Result<(), Error> {
+        let epoch = epoch_guard.receiver.borrow();
+        let age = self.chain_map.epoch_age(&epoch);
+        if age < self.min_age {
+            return Err(MembraneError::EpochTooFresh {
+                adopted_block: epoch.adopted_block,
+                age,
+                min_age: self.min_age,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
 
-    impl BundleSimulator for MockSimulator {
-        fn simulate(
-            &self,
-            _bundle: &BundleSpec,
-            _target_block: u64,
-        ) -> std::pin::Pin<
-            Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>,
-        > {
-            Box::pin(async {
-                Ok(SimResult {
-                    gas_used: 21000,
-                    success: true,
-                    state_root: vec![0xab; 32],
-                    revert_reason: String::new(),
-                })
-            })
+/// The bundle's raw transactions (held server-side, never exposed to builder).
+#[derive(Clone, Debug)]
+pub struct BundleSpec {
+    pub txs: Vec<Vec<u8>>,
+}
+
+/// EIP-2718 envelope type of a signed transaction. Legacy RLP txs (first
+/// byte `>= 0xc0`) are type `0`.
+pub fn tx_type(tx: &[u8]) -> Result<u8, Error> {
+    match tx.first() {
+        Some(&b) if b <= 0x7f => Ok(b),
+        Some(&b) if b >= 0xc0 => Ok(0),
+        Some(&b) => Err(MembraneError::InvalidTx {
+            detail: format!("unknown envelope byte {:#04x}", b),
         }
+        .into()),
+        None => Err(MembraneError::InvalidTx {
+            detail: "empty transaction".to_string(),
+        }
+        .into()),
     }
+}
 
-    fn test_epoch(seq: u64) -> Epoch {
-        Epoch {
-            seq,
-            head: vec![],
-            adopted_block: 100,
+/// Fail with `disallowedTxType` if `tx`, at position `index` in its
+/// bundle, has a type outside `allowed_tx_types`.
+pub(crate) fn check_tx_type(
+    index: usize,
+    tx: &[u8],
+    allowed_tx_types: &HashSet<u8>,
+) -> Result<(), Error> {
+    let ty = tx_type(tx)?;
+    if !allowed_tx_types.contains(&ty) {
+        return Err(MembraneError::DisallowedTxType {
+            index: index as u64,
+            tx_type: ty,
         }
+        .into());
     }
+    Ok(())
+}
 
-    fn test_server(
-        epoch_rx: watch::Receiver<Epoch>,
-        issued_seq: u64,
-    ) -> (crate::revocation::RevocationHandle, BundleAccessServer) {
-        let (handle, revocation_guard) = RevocationGuard::new();
-        let server = BundleAccessServer {
-            epoch_guard: EpochGuard {
-                issued_seq,
-                receiver: epoch_rx,
-            },
-            revocation_guard,
-            block_window: BlockWindowGuard {
-                valid_from: 100,
-                valid_until: 110,
-            },
-            bundle: BundleSpec {
-                txs: vec![vec![0x01, 0x02]],
-            },
-            simulator: Arc::new(MockSimulator),
-        };
-        (handle, server)
+/// Fail with `noReplayProtection` if `tx`, at position `index` in its
+/// bundle, is a legacy tx without an EIP-155 chain id.
+pub(crate) fn check_replay_protection(index: usize, tx: &[u8]) -> Result<(), Error> {
+    if tx_type(tx)? == 0 && matches!(crate::rlp::legacy_v(tx)?, 27 | 28) {
+        return Err(MembraneError::NoReplayProtection {
+            index: index as u64,
+        }
+        .into());
     }
+    Ok(())
+}
 
-    #[test]
-    fn check_all_passes_when_valid() {
-        let (_tx, rx) = watch::channel(test_epoch(1));
-        let (_handle, server) = test_server(rx, 1);
-        assert!(server.check_all(105).is_ok());
+impl BundleSpec {
+    /// Build a bundle, rejecting any tx whose envelope type is not in
+    /// `allowed_tx_types`.
+    pub fn try_new(txs: Vec<Vec<u8>>, allowed_tx_types: &HashSet<u8>) -> Result<Self, Error> {
+        let bundle = Self { txs };
+        bundle.check_tx_types(allowed_tx_types)?;
+        Ok(bundle)
     }
 
-    #[test]
-    fn check_all_fails_stale_epoch() {
-        let (tx, rx) = watch::channel(test_epoch(1));
-        let (_handle, server) = test_server(rx, 1);
-        tx.send(test_epoch(2)).unwrap();
-        let err = server.check_all(105).unwrap_err();
-        assert!(err.to_string().contains("staleEpoch"));
+    /// Fail with `disallowedTxType` on the first tx of a type outside
+    /// `allowed_tx_types`.
+    pub fn check_tx_types(&self, allowed_tx_types: &HashSet<u8>) -> Result<(), Error> {
+        for (index, tx) in self.txs.iter().enumerate() {
+            check_tx_type(index, tx, allowed_tx_types)?;
+        }
+        Ok(())
     }
 
-    #[test]
-    fn check_all_fails_revoked() {
-        let (_tx, rx) = watch::channel(test_epoch(1));
-        let (handle, server) = test_server(rx, 1);
-        handle.revoke();
-        let err = server.check_all(105).unwrap_err();
-        assert!(err.to_string().contains("revoked"));
+    /// Fail with `noReplayProtection` on the first legacy tx signed without
+    /// an EIP-155 chain id (`v` of 27 or 28). Typed txs always carry one.
+    pub fn check_replay_protection(&self) -> Result<(), Error> {
+        for (index, tx) in self.txs.iter().enumerate() {
+            check_replay_protection(index, tx)?;
+        }
+        Ok(())
     }
 
-    #[test]
-    fn check_all_fails_block_out_of_window() {
-        let (_tx, rx) = watch::channel(test_epoch(1));
-        let (_handle, server) = test_server(rx, 1);
-        let err = server.check_all(200).unwrap_err();
-        assert!(err.to_string().contains("blockOutOfWindow"));
+    /// keccak256 over the length-prefixed transactions; identifies the
+    /// bundle's exact contents.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut buf = Vec::new();
+        for tx in &self.txs {
+            buf.extend_from_slice(&(tx.len() as u64).to_be_bytes());
+            buf.extend_from_slice(tx);
+        }
+        keccak256(&buf)
     }
+}
 
-    #[test]
-    fn block_window_inclusive_bounds() {
-        let guard = BlockWindowGuard {
-            valid_from: 100,
-            valid_until: 110,
-        };
-        assert!(guard.check(100).is_ok()); // lower bound inclusive
-        assert!(guard.check(110).is_ok()); // upper bound inclusive
-        assert!(guard.check(99).is_err());
-        assert!(guard.check(111).is_err());
+/// Result of simulating the bundle against a target block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimResult {
+    pub gas_used: u64,
+    pub success: bool,
+    pub state_root: Vec<u8>,
+    pub revert_reason: String,
+    /// Wei the bundle paid the block's coinbase (priority fees plus direct
+    /// transfers), saturating at `u64::MAX`.
+    pub coinbase_diff: u64,
+    /// Backend that served the result. Left empty by a backend, it is
+    /// filled with the top-level simulator's `info().kind`.
+    pub simulated_by_backend: String,
+    /// Left at `0` by a backend, it is filled with the measured latency.
+    pub simulation_latency_ms: u32,
+    /// Per-tx outcomes in bundle order; empty if the backend only reports
+    /// aggregates.
+    pub tx_results: Vec<TxResult>,
+    /// Event logs in emission order; empty if none were emitted or the
+    /// backend does not capture logs.
+    pub logs: Vec<Log>,
+}
+
+impl SimResult {
+    /// keccak256 over the outcome fields (gas used, success, state root,
+    /// revert reason, coinbase diff). Backend identity, latency and per-tx
+    /// results are not covered, so reruns of an unchanged environment hash
+    /// equal.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.gas_used.to_be_bytes());
+        buf.push(self.success as u8);
+        buf.extend_from_slice(&(self.state_root.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&self.state_root);
+        buf.extend_from_slice(&(self.revert_reason.len() as u64).to_be_bytes());
+        buf.extend_from_slice(self.revert_reason.as_bytes());
+        buf.extend_from_slice(&self.coinbase_diff.to_be_bytes());
+        keccak256(&buf)
+    }
+
+    /// Names of the outcome fields (as in the schema) that differ from
+    /// `prior`.
+    pub fn changed_fields(&self, prior: &SimResult) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.gas_used != prior.gas_used {
+            changed.push("gasUsed");
+        }
+        if self.success != prior.success {
+            changed.push("success");
+        }
+        if self.state_root != prior.state_root {
+            changed.push("stateRoot");
+        }
+        if self.revert_reason != prior.revert_reason {
+            changed.push("revertReason");
+        }
+        if self.coinbase_diff != prior.coinbase_diff {
+            changed.push("coinbaseDiff");
+        }
+        changed
+    }
+}
+
+/// How many results a session remembers for `resimulateAndDiff`.
+pub const RECENT_RESULTS_CAPACITY: usize = 32;
+
+/// Results recently returned to a session, keyed by [`SimResult::hash`].
+/// Oldest entries are evicted first once [`RECENT_RESULTS_CAPACITY`] is
+/// reached.
+#[derive(Clone, Default)]
+pub struct RecentResults {
+    entries: Arc<Mutex<VecDeque<([u8; 32], SimResult)>>>,
+}
+
+impl RecentResults {
+    /// Remember `sim`, returning its hash.
+    pub fn record(&self, sim: &SimResult) -> [u8; 32] {
+        let hash = sim.hash();
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.iter().any(|(h, _)| *h == hash) {
+            if entries.len() >= RECENT_RESULTS_CAPACITY {
+                entries.pop_front();
+            }
+            entries.push_back((hash, sim.clone()));
+        }
+        hash
+    }
+
+    /// The remembered result with `hash`, if still held.
+    pub fn get(&self, hash: &[u8]) -> Option<SimResult> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .find(|(h, _)| h.as_slice() == hash)
+            .map(|(_, sim)| sim.clone())
+    }
+}
+
+/// Outcome of one transaction within a simulated bundle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxResult {
+    pub gas_used: u64,
+    pub success: bool,
+    pub revert_reason: String,
+}
+
+/// An event log emitted during simulation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Log {
+    /// 20-byte emitting contract address.
+    pub address: Vec<u8>,
+    pub topics: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+/// Bitmask of [`SimResult`] fields a grant discloses to the builder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResultFields(u16);
+
+impl ResultFields {
+    pub const NONE: Self = Self(0);
+    pub const GAS_USED: Self = Self(1 << 0);
+    pub const SUCCESS: Self = Self(1 << 1);
+    pub const STATE_ROOT: Self = Self(1 << 2);
+    pub const REVERT_REASON: Self = Self(1 << 3);
+    pub const SIMULATED_BY_BACKEND: Self = Self(1 << 4);
+    pub const SIMULATION_LATENCY: Self = Self(1 << 5);
+    /// Per-tx entries; within each, gas, success and revert reason follow
+    /// the aggregate bits above.
+    pub const TX_RESULTS: Self = Self(1 << 6);
+    pub const LOGS: Self = Self(1 << 7);
+    pub const COINBASE_DIFF: Self = Self(1 << 8);
+    pub const ALL: Self = Self(0b1_1111_1111);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for ResultFields {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl std::ops::BitOr for ResultFields {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Privacy knob: coarsens the values returned to the builder so they leak
+/// less about the bundle. The server keeps the exact result internally.
+#[derive(Clone, Debug, Default)]
+pub struct ResultQuantization {
+    /// Round `gas_used` up to a multiple of this many gas. `0` disables.
+    pub gas_bucket: u64,
+    /// Round `coinbase_diff` down to a multiple of this many wei, so the
+    /// builder never sees more than the bundle pays. `0` disables.
+    pub coinbase_bucket: u64,
+    /// Strongest setting: return only `success`; every other field is
+    /// zeroed or empty. Equivalent to `fields: ResultFields::SUCCESS`.
+    pub success_only: bool,
+    /// Fields returned to the builder; the rest are zeroed or empty.
+    pub fields: ResultFields,
+}
+
+impl ResultQuantization {
+    /// Whether `apply` returns results unchanged: no rounding, every field.
+    pub fn is_identity(&self) -> bool {
+        self.gas_bucket == 0
+            && self.coinbase_bucket == 0
+            && !self.success_only
+            && self.fields == ResultFields::ALL
+    }
+
+    /// Return the builder-facing copy of `sim`.
+    pub fn apply(&self, sim: &SimResult) -> SimResult {
+        let fields = if self.success_only {
+            ResultFields::SUCCESS
+        } else {
+            self.fields
+        };
+        let mut out = SimResult::default();
+        if fields.contains(ResultFields::GAS_USED) {
+            out.gas_used = self.bucket_gas(sim.gas_used);
+        }
+        if fields.contains(ResultFields::SUCCESS) {
+            out.success = sim.success;
+        }
+        if fields.contains(ResultFields::STATE_ROOT) {
+            out.state_root = sim.state_root.clone();
+        }
+        if fields.contains(ResultFields::REVERT_REASON) {
+            out.revert_reason = sim.revert_reason.clone();
+        }
+        if fields.contains(ResultFields::SIMULATED_BY_BACKEND) {
+            out.simulated_by_backend = sim.simulated_by_backend.clone();
+        }
+        if fields.contains(ResultFields::SIMULATION_LATENCY) {
+            out.simulation_latency_ms = sim.simulation_latency_ms;
+        }
+        if fields.contains(ResultFields::TX_RESULTS) {
+            out.tx_results = sim
+                .tx_results
+                .iter()
+                .map(|tx| TxResult {
+                    gas_used: if fields.contains(ResultFields::GAS_USED) {
+                        self.bucket_gas(tx.gas_used)
+                    } else {
+                        0
+                    },
+                    success: fields.contains(ResultFields::SUCCESS) && tx.success,
+                    revert_reason: if fields.contains(ResultFields::REVERT_REASON) {
+                        tx.revert_reason.clone()
+                    } else {
+                        String::new()
+                    },
+                })
+                .collect();
+        }
+        if fields.contains(ResultFields::LOGS) {
+            out.logs = sim.logs.clone();
+        }
+        if fields.contains(ResultFields::COINBASE_DIFF) {
+            out.coinbase_diff = match self.coinbase_bucket {
+                0 => sim.coinbase_diff,
+                bucket => sim.coinbase_diff - sim.coinbase_diff % bucket,
+            };
+        }
+        out
+    }
+
+    fn bucket_gas(&self, gas_used: u64) -> u64 {
+        if self.gas_bucket == 0 {
+            return gas_used;
+        }
+        gas_used
+            .div_ceil(self.gas_bucket)
+            .saturating_mul(self.gas_bucket)
+    }
+}
+
+/// Backend characteristics reported to the builder via `simulatorInfo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulatorInfo {
+    /// Backend name, e.g. `"eth_call"` or `"revm"`.
+    pub kind: String,
+    pub chain_id: u64,
+    /// Whether txs execute against each other's state changes.
+    pub full_evm: bool,
+}
+
+impl Default for SimulatorInfo {
+    fn default() -> Self {
+        Self {
+            kind: "unknown".to_string(),
+            chain_id: 0,
+            full_evm: false,
+        }
+    }
+}
+
+/// State a pinned grant simulates against: the head at issuance. `hash` is
+/// set when the grant's chain map knows it, so a backend that addresses
+/// state by hash keeps using the same block across a reorg.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinnedState {
+    pub block: u64,
+    pub hash: Option<Vec<u8>>,
+}
+
+/// Abstraction over the simulation backend.
+pub trait BundleSimulator: Send + Sync + 'static {
+    fn simulate(
+        &self,
+        bundle: &BundleSpec,
+        target_block: u64,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>;
+
+    /// Describe this backend. Defaults to an `"unknown"`, non-full-EVM
+    /// backend so builders don't over-trust unlabelled results.
+    fn info(&self) -> SimulatorInfo {
+        SimulatorInfo::default()
+    }
+
+    /// Call trace of `bundle` at `target_block`, in the backend's own
+    /// encoding. Backends that cannot trace keep the default, which fails
+    /// with `unimplemented`.
+    fn trace(
+        &self,
+        _bundle: &BundleSpec,
+        _target_block: u64,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<u8>, Error>> + Send>> {
+        Box::pin(async {
+            Err(Error::unimplemented(
+                "traceUnsupported: simulator backend cannot trace".to_string(),
+            ))
+        })
+    }
+
+    /// Simulate `bundle` against pinned `state`. Backends that can address
+    /// state by block hash should override this to use `state.hash`; the
+    /// default simulates at `state.block`.
+    fn simulate_pinned(
+        &self,
+        bundle: &BundleSpec,
+        state: &PinnedState,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>> {
+        self.simulate(bundle, state.block)
+    }
+
+    /// [`trace`](Self::trace) against pinned `state`; see
+    /// [`simulate_pinned`](Self::simulate_pinned).
+    fn trace_pinned(
+        &self,
+        bundle: &BundleSpec,
+        state: &PinnedState,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<u8>, Error>> + Send>> {
+        self.trace(bundle, state.block)
+    }
+}
+
+/// BundleAccess schema version spoken by this server.
+pub const SCHEMA_VERSION: u32 = 12;
+
+/// Methods and result fields, with the schema version that introduced
+/// each. Methods are refused, and result fields left unset, for sessions
+/// that negotiated an older version.
+const FEATURES: &[(&str, u32)] = &[
+    ("simulate", 1),
+    ("include", 1),
+    ("simulateDiff", 2),
+    ("isValid", 3),
+    ("simulatedBlock", 3),
+    ("simulatorInfo", 4),
+    ("simulatedByBackend", 4),
+    ("simulationLatencyMs", 4),
+    ("txResults", 4),
+    ("trace", 5),
+    ("resimulateAndDiff", 6),
+    ("resultHash", 6),
+    ("simulateRange", 7),
+    ("status", 8),
+    ("logs", 8),
+    ("includePreflight", 9),
+    ("coinbaseDiff", 10),
+    ("statusLatency", 11),
+    ("resultAttestation", 12),
+];
+
+/// Traces smaller than this are sent uncompressed even when the grant
+/// compresses traces.
+pub const TRACE_COMPRESSION_THRESHOLD: usize = 4096;
+
+/// zstd-compress `frames` if they reach [`TRACE_COMPRESSION_THRESHOLD`];
+/// the flag reports whether they were.
+fn compress_trace(frames: Vec<u8>) -> Result<(Vec<u8>, bool), Error> {
+    if frames.len() < TRACE_COMPRESSION_THRESHOLD {
+        return Ok((frames, false));
+    }
+    let packed = zstd::bulk::compress(&frames, 0)
+        .map_err(|e| Error::failed(format!("traceCompression: {}", e)))?;
+    Ok((packed, true))
+}
+
+/// Client side: recover trace frames from a `trace` response.
+pub fn decompress_trace(frames: &[u8], compressed: bool) -> Result<Vec<u8>, Error> {
+    if !compressed {
+        return Ok(frames.to_vec());
+    }
+    zstd::stream::decode_all(frames).map_err(|e| Error::failed(format!("traceCompression: {}", e)))
+}
+
+/// Most blocks a single `simulateRange` may cover.
+pub const MAX_SIMULATE_RANGE: u64 = 16;
+
+/// Whether `feature` is available to a client speaking `version`.
+fn supports(version: u32, feature: &str) -> bool {
+    FEATURES
+        .iter()
+        .any(|(name, since)| *name == feature && *since <= version)
+}
+
+/// Features available to a client speaking `version`.
+pub fn features_for(version: u32) -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, since)| *since <= version)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The capability server that implements BundleAccess.
+///
+/// Every method call checks three guards in sequence:
+/// 1. EpochGuard — is the session epoch still current?
+/// 2. RevocationGuard — has the searcher revoked?
+/// 3. BlockWindowGuard — is the target block in range?
+pub struct BundleAccessServer {
+    pub epoch_guard: EpochGuard,
+    pub revocation_guard: RevocationGuard,
+    pub block_window: BlockWindowGuard,
+    /// Optional wall-clock expiry, checked alongside the block window.
+    pub time_window: Option<TimeWindowGuard>,
+    /// When set, every simulation and trace runs against this state
+    /// whatever the target; targets are still window-checked.
+    pub pinned_state: Option<PinnedState>,
+    /// Read per call, so searcher-side changes apply to later calls.
+    pub bundle: BundleHandle,
+    pub simulator: Arc<dyn BundleSimulator>,
+    pub audit: Option<Arc<dyn AuditSink>>,
+    /// When set, a sampled fraction of simulations is persisted in full
+    /// to `audit`.
+    pub audit_sampler: Option<AuditSampler>,
+    pub quantization: ResultQuantization,
+    /// When set, `include` requires a prior successful `simulate`.
+    pub simulate_first: Option<SimulateFirstGuard>,
+    /// When set, the bundle may be included at one block only.
+    pub inclusion: Option<InclusionGuard>,
+    /// When set, `include` waits for the epoch to age.
+    pub epoch_age: Option<EpochAgeGuard>,
+    /// When set, a simulation using more gas is reported as failed with
+    /// revert reason `gasLimitExceeded`, so it also can't satisfy
+    /// simulate-first.
+    pub max_gas: Option<u64>,
+    /// When set, every simulate's latency is recorded here.
+    pub latency: Option<LatencyTracker>,
+    /// When set, simulations are counted so shutdown can drain them.
+    pub in_flight: Option<InFlightTracker>,
+    /// Schema version agreed via `negotiate`; starts at [`SCHEMA_VERSION`].
+    pub negotiated_version: Cell<u32>,
+    /// Opt-in: clamp an out-of-window simulate target to the nearest valid
+    /// block instead of rejecting it. The block used is reported back.
+    pub clamp_to_window: bool,
+    /// Opt-in: zstd-compress large traces; see `trace` in the schema.
+    pub compress_traces: bool,
+    /// Cap on simulations in progress at once across every session of the
+    /// grant, counted in `active_calls`, independently of any registry-wide
+    /// `in_flight` tracker.
+    pub max_concurrent_calls: Option<usize>,
+    /// Deadline for each backend simulation; a hung backend fails the call
+    /// with `simTimeout` instead of holding its slot forever.
+    pub simulate_timeout: Option<std::time::Duration>,
+    /// Simulating calls in progress; shared by every session minted from
+    /// the same grant, so grafting again does not buy more parallelism.
+    pub active_calls: InFlightTracker,
+    /// When set, every simulated block and include spends one call.
+    pub call_budget: Option<CallBudgetGuard>,
+    /// When set, simulated blocks and includes are rate limited.
+    pub rate_limit: Option<RateLimitGuard>,
+    /// When set, told about every guard decision.
+    pub guard_observer: Option<Arc<dyn GuardObserver>>,
+    /// Results returned to this session, for `resimulateAndDiff`.
+    pub recent_results: RecentResults,
+    /// When set, `simulate` attests each result hash with this.
+    pub attestor: Option<Arc<dyn Authenticator>>,
+}
+
+impl BundleAccessServer {
+    /// Fail with `unimplemented` if the negotiated version predates `feature`.
+    fn require_feature(&self, feature: &str) -> Result<(), Error> {
+        if !supports(self.negotiated_version.get(), feature) {
+            return Err(MembraneError::FeatureUnavailable {
+                feature: feature.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Admit a simulating call, failing if the grant is already at its
+    /// concurrency cap. The call counts until the permit is dropped.
+    /// Callers admit before spending, so a refused call costs nothing.
+    fn enter_call(&self) -> Result<InFlightPermit, Error> {
+        if let Some(max) = self.max_concurrent_calls {
+            if self.active_calls.count() >= max {
+                return Err(MembraneError::TooManyConcurrentCalls { limit: max as u64 }.into());
+            }
+        }
+        Ok(self.active_calls.enter())
+    }
+
+    /// Report a guard `outcome` for `method` to the observer, if any, and
+    /// pass it through.
+    fn observe(
+        &self,
+        method: &str,
+        target_block: u64,
+        outcome: Result<(), Error>,
+    ) -> Result<(), Error> {
+        if let Some(observer) = &self.guard_observer {
+            observer.on_check(method, target_block, &outcome);
+        }
+        outcome
+    }
+
+    /// Check all guards before processing any method call, taking a
+    /// rate-limit token and spending one call from the budget if the grant
+    /// has them.
+    fn check_all(&self, target_block: u64) -> Result<(), Error> {
+        self.check_guards(target_block)?;
+        self.spend(1)
+    }
+
+    /// Spend `calls` calls from the call budget and rate limit, all or
+    /// nothing. Callers run every non-consuming guard first, so a refused
+    /// call costs nothing.
+    fn spend(&self, calls: u32) -> Result<(), Error> {
+        if let Some(budget) = &self.call_budget {
+            budget.consume_n(u64::from(calls))?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            if let Err(e) = limit.take(calls) {
+                if let Some(budget) = &self.call_budget {
+                    budget.refund(u64::from(calls));
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The guards in `check_all`, without spending from the call budget.
+    fn check_guards(&self, target_block: u64) -> Result<(), Error> {
+        self.epoch_guard.check()?;
+        self.revocation_guard.check()?;
+        self.block_window.check(target_block)?;
+        if let Some(time_window) = &self.time_window {
+            time_window.check()?;
+        }
+        if let Some(budget) = &self.call_budget {
+            budget.check()?;
+        }
+        Ok(())
+    }
+
+    /// Run the simulator against `target_block` (or the pinned state),
+    /// recording latency, audit (including any sample) and simulate-first
+    /// state. Resolves to the exact (unquantized) result,
+    /// with backend identity and latency filled in if the backend left
+    /// them unset.
+    fn run_simulation(
+        &self,
+        target_block: u64,
+    ) -> impl std::future::Future<Output = Result<SimResult, Error>> + 'static {
+        let started = std::time::Instant::now();
+        let bundle = self.bundle.snapshot();
+        let fut = match &self.pinned_state {
+            Some(state) => self.simulator.simulate_pinned(&bundle, state),
+            None => self.simulator.simulate(&bundle, target_block),
+        };
+        let sink = self.audit.clone();
+        let sampled = self
+            .audit_sampler
+            .as_ref()
+            .is_some_and(AuditSampler::sample);
+        let bundle_hash = sampled.then(|| bundle.hash());
+        let simulate_first = self.simulate_first.clone();
+        let latency = self.latency.clone();
+        let permit = self.in_flight.as_ref().map(InFlightTracker::enter);
+        let backend = self.simulator.info().kind;
+        let deadline = self.simulate_timeout;
+        let max_gas = self.max_gas;
+
+        async move {
+            let sim = match deadline {
+                Some(deadline) => tokio::time::timeout(deadline, fut)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(MembraneError::SimTimeout {
+                            timeout_ms: u64::try_from(deadline.as_millis()).unwrap_or(u64::MAX),
+                        }
+                        .into())
+                    }),
+                None => fut.await,
+            };
+            drop(permit);
+            let elapsed = started.elapsed();
+            if let Some(latency) = &latency {
+                latency.record(elapsed);
+            }
+            let mut sim = sim?;
+            if sim.simulated_by_backend.is_empty() {
+                sim.simulated_by_backend = backend;
+            }
+            if sim.simulation_latency_ms == 0 {
+                sim.simulation_latency_ms = u32::try_from(elapsed.as_millis()).unwrap_or(u32::MAX);
+            }
+            if max_gas.is_some_and(|max| sim.gas_used > max) {
+                sim.success = false;
+                sim.revert_reason = "gasLimitExceeded".to_string();
+            }
+            if sim.success {
+                if let Some(guard) = &simulate_first {
+                    guard.record(target_block);
+                }
+            }
+            record_audit(
+                &sink,
+                AuditEvent::Simulate {
+                    target_block,
+                    success: sim.success,
+                },
+            );
+            if let Some(bundle_hash) = bundle_hash {
+                record_audit(
+                    &sink,
+                    AuditEvent::SimulationSample {
+                        bundle_hash,
+                        target_block,
+                        result: sim.clone(),
+                    },
+                );
+            }
+            Ok(sim)
+        }
+    }
+
+    /// Guards for `include`: every check in `check_include_guards`, then
+    /// pinning the inclusion block, then spending the call. A refused
+    /// include costs nothing: one that loses a race to pin another block
+    /// has spent nothing, and one refused by the rate limit gives back the
+    /// pin it took.
+    fn check_include(&self, target_block: u64) -> Result<(), Error> {
+        self.check_include_guards(target_block)?;
+        let pinned = match &self.inclusion {
+            Some(guard) => guard.pin(target_block)?,
+            None => false,
+        };
+        if let Err(e) = self.spend(1) {
+            if let Some(guard) = self.inclusion.as_ref().filter(|_| pinned) {
+                guard.unpin(target_block);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The guards in `check_guards`, plus the include-only ones
+    /// (simulate-first, epoch age, single inclusion), without spending
+    /// from the call budget or pinning the inclusion.
+    fn check_include_guards(&self, target_block: u64) -> Result<(), Error> {
+        self.check_guards(target_block)?;
+        if let Some(guard) = &self.simulate_first {
+            guard.check(target_block)?;
+        }
+        if let Some(guard) = &self.epoch_age {
+            guard.check(&self.epoch_guard)?;
+        }
+        if let Some(guard) = &self.inclusion {
+            guard.check(target_block)?;
+        }
+        Ok(())
+    }
+}
+
+/// `b - a`, clamped to the `i64` range of the wire field.
+fn saturating_delta(a: u64, b: u64) -> i64 {
+    (i128::from(b) - i128::from(a)).clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+/// Write a [`SimResult`] into its capnp builder, leaving unset the fields
+/// schema `version` predates.
+fn fill_sim_result(mut r: bundle_capnp::sim_result::Builder<'_>, sim: &SimResult, version: u32) {
+    r.set_gas_used(sim.gas_used);
+    r.set_success(sim.success);
+    r.set_state_root(&sim.state_root);
+    r.set_revert_reason(&sim.revert_reason);
+    if supports(version, "coinbaseDiff") {
+        r.set_coinbase_diff(sim.coinbase_diff);
+    }
+    if supports(version, "simulatedByBackend") {
+        r.set_simulated_by_backend(&sim.simulated_by_backend);
+    }
+    if supports(version, "simulationLatencyMs") {
+        r.set_simulation_latency_ms(sim.simulation_latency_ms);
+    }
+    if supports(version, "txResults") {
+        fill_tx_results(r.reborrow(), sim);
+    }
+    if supports(version, "logs") {
+        fill_logs(r, sim);
+    }
+}
+
+fn fill_tx_results(r: bundle_capnp::sim_result::Builder<'_>, sim: &SimResult) {
+    let mut txs = r.init_tx_results(sim.tx_results.len() as u32);
+    for (i, tx) in sim.tx_results.iter().enumerate() {
+        let mut t = txs.reborrow().get(i as u32);
+        t.set_gas_used(tx.gas_used);
+        t.set_success(tx.success);
+        t.set_revert_reason(&tx.revert_reason);
+    }
+}
+
+fn fill_logs(r: bundle_capnp::sim_result::Builder<'_>, sim: &SimResult) {
+    let mut logs = r.init_logs(sim.logs.len() as u32);
+    for (i, log) in sim.logs.iter().enumerate() {
+        let mut l = logs.reborrow().get(i as u32);
+        l.set_address(&log.address);
+        l.set_data(&log.data);
+        let mut topics = l.init_topics(log.topics.len() as u32);
+        for (j, topic) in log.topics.iter().enumerate() {
+            topics.set(j as u32, topic);
+        }
+    }
+}
+
+#[allow(refining_impl_trait)]
+impl bundle_capnp::bundle_access::Server for BundleAccessServer {
+    fn simulate(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::SimulateParams,
+        mut results: bundle_capnp::bundle_access::SimulateResults,
+    ) -> Promise<(), Error> {
+        let mut target_block = pry!(params.get()).get_target_block();
+        if self.clamp_to_window {
+            target_block = self.block_window.clamp(target_block);
+        }
+        let call = pry!(self.enter_call());
+        pry!(self.observe("simulate", target_block, self.check_all(target_block)));
+
+        let sim = self.run_simulation(target_block);
+        let quantization = self.quantization.clone();
+        let recent = self.recent_results.clone();
+        let version = self.negotiated_version.get();
+        let attestor = self.attestor.clone();
+
+        Promise::from_future(async move {
+            let sim = sim.await;
+            drop(call);
+            let sim = quantization.apply(&sim?);
+            let mut r = results.get();
+            let hash = recent.record(&sim);
+            if supports(version, "simulatedBlock") {
+                r.set_simulated_block(target_block);
+            }
+            if supports(version, "resultHash") {
+                r.set_result_hash(&hash);
+            }
+            if let Some(attestor) = attestor.filter(|_| supports(version, "resultAttestation")) {
+                r.set_attestation(&attestor.sign(&hash)?);
+            }
+            fill_sim_result(r.init_result(), &sim, version);
+            Ok(())
+        })
+    }
+
+    fn simulate_diff(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::SimulateDiffParams,
+        mut results: bundle_capnp::bundle_access::SimulateDiffResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("simulateDiff"));
+        let params = pry!(params.get());
+        let (block_a, block_b) = (params.get_block_a(), params.get_block_b());
+        let call = pry!(self.enter_call());
+        // Both blocks must pass before either is paid for.
+        pry!(self.observe("simulateDiff", block_a, self.check_guards(block_a)));
+        let outcome = self.check_guards(block_b).and_then(|()| self.spend(2));
+        pry!(self.observe("simulateDiff", block_b, outcome));
+
+        let sim_a = self.run_simulation(block_a);
+        let sim_b = self.run_simulation(block_b);
+        let quantization = self.quantization.clone();
+        let version = self.negotiated_version.get();
+
+        Promise::from_future(async move {
+            let _call = call;
+            let a = quantization.apply(&sim_a.await?);
+            let b = quantization.apply(&sim_b.await?);
+            let mut r = results.get();
+            r.set_gas_delta(saturating_delta(a.gas_used, b.gas_used));
+            if supports(version, "coinbaseDiff") {
+                r.set_value_delta(saturating_delta(a.coinbase_diff, b.coinbase_diff));
+            }
+            fill_sim_result(r.reborrow().init_result_a(), &a, version);
+            fill_sim_result(r.init_result_b(), &b, version);
+            Ok(())
+        })
+    }
+
+    fn simulate_range(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::SimulateRangeParams,
+        mut results: bundle_capnp::bundle_access::SimulateRangeResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("simulateRange"));
+        let params = pry!(params.get());
+        let (from, to) = (params.get_from_block(), params.get_to_block());
+        if from > to {
+            return Promise::err(MembraneError::InvalidRange { from, to }.into());
+        }
+        if to - from >= MAX_SIMULATE_RANGE {
+            return Promise::err(
+                MembraneError::RangeTooLarge {
+                    requested: (to - from).saturating_add(1),
+                    max: MAX_SIMULATE_RANGE,
+                }
+                .into(),
+            );
+        }
+        let call = pry!(self.enter_call());
+        // Every block must pass before any is paid for; then the whole
+        // range is paid for at once.
+        for block in from..=to {
+            let mut outcome = self.check_guards(block);
+            if block == to {
+                outcome = outcome.and_then(|()| self.spend((to - from + 1) as u32));
+            }
+            pry!(self.observe("simulateRange", block, outcome));
+        }
+
+        let sims: Vec<_> = (from..=to).map(|b| self.run_simulation(b)).collect();
+        let quantization = self.quantization.clone();
+        let version = self.negotiated_version.get();
+
+        Promise::from_future(async move {
+            let sims = futures::future::join_all(sims).await;
+            drop(call);
+            let mut list = results.get().init_results(sims.len() as u32);
+            for (i, sim) in sims.into_iter().enumerate() {
+                let sim = quantization.apply(&sim?);
+                fill_sim_result(list.reborrow().get(i as u32), &sim, version);
+            }
+            Ok(())
+        })
+    }
+
+    fn include(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::IncludeParams,
+        mut results: bundle_capnp::bundle_access::IncludeResults,
+    ) -> Promise<(), Error> {
+        let target_block = pry!(params.get()).get_target_block();
+        pry!(self.observe("include", target_block, self.check_include(target_block)));
+        record_audit(&self.audit, AuditEvent::Include { target_block });
+        results.get().set_included(true);
+        Promise::ok(())
+    }
+
+    fn is_valid(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::IsValidParams,
+        mut results: bundle_capnp::bundle_access::IsValidResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("isValid"));
+        let target_block = pry!(params.get()).get_target_block();
+        let mut r = results.get();
+        match self.observe("isValid", target_block, self.check_guards(target_block)) {
+            Ok(()) => r.set_valid(true),
+            Err(e) => {
+                r.set_valid(false);
+                r.set_reason(e.extra.as_str());
+            }
+        }
+        Promise::ok(())
+    }
+
+    fn simulator_info(
+        self: capnp::capability::Rc<Self>,
+        _params: bundle_capnp::bundle_access::SimulatorInfoParams,
+        mut results: bundle_capnp::bundle_access::SimulatorInfoResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("simulatorInfo"));
+        pry!(self.epoch_guard.check());
+        pry!(self.revocation_guard.check());
+        let info = self.simulator.info();
+        let mut r = results.get();
+        r.set_kind(info.kind.as_str());
+        r.set_chain_id(info.chain_id);
+        r.set_full_evm(info.full_evm);
+        Promise::ok(())
+    }
+
+    fn trace(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::TraceParams,
+        mut results: bundle_capnp::bundle_access::TraceResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("trace"));
+        // A trace exposes everything quantization would hide.
+        if !self.quantization.is_identity() {
+            return Promise::err(MembraneError::TraceUnavailable.into());
+        }
+        let target_block = pry!(params.get()).get_target_block();
+        let call = pry!(self.enter_call());
+        pry!(self.observe("trace", target_block, self.check_all(target_block)));
+        let permit = self.in_flight.as_ref().map(InFlightTracker::enter);
+        let bundle = self.bundle.snapshot();
+        let frames = match &self.pinned_state {
+            Some(state) => self.simulator.trace_pinned(&bundle, state),
+            None => self.simulator.trace(&bundle, target_block),
+        };
+        let compress = self.compress_traces;
+
+        Promise::from_future(async move {
+            let frames = frames.await;
+            drop((call, permit));
+            let (frames, compressed) = if compress {
+                compress_trace(frames?)?
+            } else {
+                (frames?, false)
+            };
+            let mut r = results.get();
+            r.set_frames(&frames);
+            r.set_compressed(compressed);
+            Ok(())
+        })
+    }
+
+    fn resimulate_and_diff(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::ResimulateAndDiffParams,
+        mut results: bundle_capnp::bundle_access::ResimulateAndDiffResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("resimulateAndDiff"));
+        let params = pry!(params.get());
+        let target_block = params.get_target_block();
+        // Prior results are the quantized ones the builder saw, so the
+        // diff never reveals more than the grant discloses.
+        let prior_hash = pry!(params.get_prior_result_hash());
+        let prior = self.recent_results.get(prior_hash);
+        let call = pry!(self.enter_call());
+        // An unknown prior result is refused before anything is spent.
+        let outcome = self.check_guards(target_block).and_then(|()| {
+            if prior.is_some() {
+                self.spend(1)
+            } else {
+                Ok(())
+            }
+        });
+        pry!(self.observe("resimulateAndDiff", target_block, outcome));
+        let Some(prior) = prior else {
+            return Promise::err(MembraneError::UnknownResult.into());
+        };
+
+        let sim = self.run_simulation(target_block);
+        let quantization = self.quantization.clone();
+        let recent = self.recent_results.clone();
+        let version = self.negotiated_version.get();
+
+        Promise::from_future(async move {
+            let sim = sim.await;
+            drop(call);
+            let sim = quantization.apply(&sim?);
+            let mut changed = sim.changed_fields(&prior);
+            changed.retain(|name| *name != "coinbaseDiff" || supports(version, name));
+            let mut r = results.get();
+            r.set_result_hash(&recent.record(&sim));
+            fill_sim_result(r.reborrow().init_result(), &sim, version);
+            let mut list = r.init_changed_fields(changed.len() as u32);
+            for (i, name) in changed.iter().enumerate() {
+                list.set(i as u32, *name);
+            }
+            Ok(())
+        })
+    }
+
+    fn status(
+        self: capnp::capability::Rc<Self>,
+        _params: bundle_capnp::bundle_access::StatusParams,
+        mut results: bundle_capnp::bundle_access::StatusResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("status"));
+        let mut r = results.get();
+        r.set_revoked(self.revocation_guard.check().is_err());
+        r.set_epoch_current(self.epoch_guard.check().is_ok());
+        r.set_valid_from(self.block_window.valid_from);
+        r.set_valid_until(self.block_window.valid_until);
+        if let Some(latency) = &self.latency {
+            if supports(self.negotiated_version.get(), "statusLatency") {
+                let micros = |d: Option<std::time::Duration>| d.map_or(0, |d| d.as_micros() as u64);
+                r.set_latency_p50_micros(micros(latency.p50()));
+                r.set_latency_p99_micros(micros(latency.p99()));
+            }
+        }
+        Promise::ok(())
+    }
+
+    fn include_preflight(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::IncludePreflightParams,
+        mut results: bundle_capnp::bundle_access::IncludePreflightResults,
+    ) -> Promise<(), Error> {
+        pry!(self.require_feature("includePreflight"));
+        let target_block = pry!(params.get()).get_target_block();
+        let mut r = results.get();
+        let outcome = self.check_include_guards(target_block);
+        match self.observe("includePreflight", target_block, outcome) {
+            Ok(()) => r.set_allowed(true),
+            Err(e) => {
+                r.set_allowed(false);
+                r.set_reason(e.extra.as_str());
+            }
+        }
+        Promise::ok(())
+    }
+
+    fn negotiate(
+        self: capnp::capability::Rc<Self>,
+        params: bundle_capnp::bundle_access::NegotiateParams,
+        mut results: bundle_capnp::bundle_access::NegotiateResults,
+    ) -> Promise<(), Error> {
+        let client_version = pry!(params.get()).get_client_version();
+        let version = client_version.min(SCHEMA_VERSION);
+        self.negotiated_version.set(version);
+
+        let features = features_for(version);
+        let mut r = results.get();
+        r.set_server_version(SCHEMA_VERSION);
+        let mut list = r.init_features(features.len() as u32);
+        for (i, name) in features.iter().enumerate() {
+            list.set(i as u32, *name);
+        }
+        Promise::ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use membrane_core::epoch::Epoch;
+    use tokio::sync::watch;
+
+    struct MockSimulator;
+
+    impl BundleSimulator for MockSimulator {
+        fn simulate(
+            &self,
+            bundle: &BundleSpec,
+            _target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            let tx_results: Vec<TxResult> = bundle
+                .txs
+                .iter()
+                .map(|_| TxResult {
+                    gas_used: 21000,
+                    success: true,
+                    revert_reason: String::new(),
+                })
+                .collect();
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used: 21000 * tx_results.len() as u64,
+                    success: true,
+                    state_root: vec![0xab; 32],
+                    tx_results,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    /// Gas scales with the target block, so different blocks differ.
+    struct BlockGasSimulator;
+
+    impl BundleSimulator for BlockGasSimulator {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used: target_block * 100,
+                    success: true,
+                    ..Default::default()
+                })
+            })
+        }
+
+        fn info(&self) -> SimulatorInfo {
+            SimulatorInfo {
+                kind: "revm".to_string(),
+                chain_id: 1,
+                full_evm: true,
+            }
+        }
+
+        fn trace(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<u8>, Error>> + Send>>
+        {
+            let frames = format!("{{\"block\":{}}}", target_block).into_bytes();
+            Box::pin(async move { Ok(frames) })
+        }
+    }
+
+    /// 21000 gas per tx, so gas tracks the bundle's length.
+    struct PerTxGasSimulator;
+
+    impl BundleSimulator for PerTxGasSimulator {
+        fn simulate(
+            &self,
+            bundle: &BundleSpec,
+            _target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            let gas_used = 21_000 * bundle.txs.len() as u64;
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used,
+                    success: true,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    fn test_epoch(seq: u64) -> Epoch {
+        Epoch {
+            seq,
+            head: vec![],
+            adopted_block: 100,
+        }
+    }
+
+    fn test_server(
+        epoch_rx: watch::Receiver<Epoch>,
+        issued_seq: u64,
+    ) -> (crate::revocation::RevocationHandle, BundleAccessServer) {
+        let (handle, revocation_guard) = RevocationGuard::new();
+        let server = BundleAccessServer {
+            epoch_guard: EpochGuard {
+                issued_seq,
+                receiver: epoch_rx,
+            },
+            revocation_guard,
+            block_window: BlockWindowGuard::single(100, 110),
+            time_window: None,
+            pinned_state: None,
+            bundle: BundleSpec {
+                txs: vec![vec![0x01, 0x02]],
+            }
+            .into(),
+            simulator: Arc::new(MockSimulator),
+            audit: None,
+            audit_sampler: None,
+            quantization: ResultQuantization::default(),
+            simulate_first: None,
+            inclusion: Some(InclusionGuard::default()),
+            epoch_age: None,
+            max_gas: None,
+            latency: None,
+            in_flight: None,
+            negotiated_version: Cell::new(SCHEMA_VERSION),
+            clamp_to_window: false,
+            compress_traces: false,
+            max_concurrent_calls: None,
+            simulate_timeout: None,
+            active_calls: InFlightTracker::default(),
+            call_budget: None,
+            rate_limit: None,
+            guard_observer: None,
+            recent_results: RecentResults::default(),
+            attestor: None,
+        };
+        (handle, server)
+    }
+
+    #[test]
+    fn check_all_passes_when_valid() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        assert!(server.check_all(105).is_ok());
+    }
+
+    #[test]
+    fn check_all_fails_stale_epoch() {
+        let (tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        tx.send(test_epoch(2)).unwrap();
+        let err = server.check_all(105).unwrap_err();
+        assert!(err.to_string().contains("staleEpoch"));
+    }
+
+    #[test]
+    fn check_all_fails_revoked() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (handle, server) = test_server(rx, 1);
+        handle.revoke();
+        let err = server.check_all(105).unwrap_err();
+        assert!(err.to_string().contains("revoked"));
+    }
+
+    #[test]
+    fn check_all_fails_block_out_of_window() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let err = server.check_all(200).unwrap_err();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+    }
+
+    #[test]
+    fn block_window_inclusive_bounds() {
+        let guard = BlockWindowGuard::single(100, 110);
+        assert!(guard.check(100).is_ok()); // lower bound inclusive
+        assert!(guard.check(110).is_ok()); // upper bound inclusive
+        assert!(guard.check(99).is_err());
+        assert!(guard.check(111).is_err());
+    }
+
+    #[test]
+    fn block_window_multi_ranges() {
+        let guard = BlockWindowGuard::multi(vec![(120, 121), (100, 102)]);
+        assert_eq!((guard.valid_from, guard.valid_until), (100, 121));
+        assert!(guard.check(100).is_ok());
+        assert!(guard.check(102).is_ok());
+        assert!(guard.check(121).is_ok());
+        let err = guard.check(110).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("target 110 not in [100, 102], [120, 121]"));
+
+        assert_eq!(guard.clamp(105), 102);
+        assert_eq!(guard.clamp(118), 120);
+        assert_eq!(guard.clamp(101), 101);
+    }
+
+    #[test]
+    fn quantization_rounds_returned_gas_only() {
+        let exact = SimResult {
+            gas_used: 21_001,
+            success: true,
+            state_root: vec![0xab; 32],
+            ..Default::default()
+        };
+        let q = ResultQuantization {
+            gas_bucket: 10_000,
+            ..Default::default()
+        };
+        let returned = q.apply(&exact);
+        assert_eq!(returned.gas_used, 30_000);
+        assert_eq!(exact.gas_used, 21_001); // server copy stays exact
+        assert_eq!(returned.success, exact.success);
+
+        let off = ResultQuantization::default();
+        assert_eq!(off.apply(&exact).gas_used, 21_001);
+    }
+
+    #[tokio::test]
+    async fn guards_decide_on_exact_values_not_quantized_ones() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockPaymentSimulator);
+        server.quantization.gas_bucket = 100_000;
+        server.quantization.coinbase_bucket = 1_000_000_000_000;
+        // The exact 21,000 gas fits; the quantized 100,000 would not.
+        server.max_gas = Some(21_000);
+        server.simulate_first = Some(SimulateFirstGuard::default());
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        assert_eq!(r.get_gas_used(), 100_000);
+        assert_eq!(r.get_coinbase_diff(), 0); // 105 gwei, rounded down
+        assert!(r.get_success());
+
+        include(&client, 105).await.unwrap();
+    }
+
+    #[test]
+    fn coinbase_diff_rounds_down_to_its_bucket() {
+        let exact = SimResult {
+            gas_used: 21_000,
+            success: true,
+            coinbase_diff: 2_500_000_000,
+            ..Default::default()
+        };
+        let q = ResultQuantization {
+            coinbase_bucket: 1_000_000_000,
+            ..Default::default()
+        };
+        assert_eq!(q.apply(&exact).coinbase_diff, 2_000_000_000);
+        assert_eq!(exact.coinbase_diff, 2_500_000_000);
+        assert_eq!(
+            ResultQuantization::default().apply(&exact).coinbase_diff,
+            2_500_000_000
+        );
+    }
+
+    #[test]
+    fn include_requires_prior_simulate_when_enabled() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        assert!(server.check_include(105).is_ok()); // opt-in: off by default
+
+        let guard = SimulateFirstGuard::default();
+        server.simulate_first = Some(guard.clone());
+        let err = server.check_include(105).unwrap_err();
+        assert!(err.to_string().contains("simulateRequiredFirst"));
+
+        guard.record(104); // different block does not count
+        assert!(server.check_include(105).is_err());
+        guard.record(105);
+        assert!(server.check_include(105).is_ok());
+    }
+
+    async fn query_status(client: &bundle_capnp::bundle_access::Client) -> (bool, bool, u64, u64) {
+        let resp = client.status_request().send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        (
+            r.get_revoked(),
+            r.get_epoch_current(),
+            r.get_valid_from(),
+            r.get_valid_until(),
+        )
+    }
+
+    #[tokio::test]
+    async fn status_reports_guard_state_without_failing() {
+        let (tx, rx) = watch::channel(test_epoch(1));
+        let (handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        assert_eq!(query_status(&client).await, (false, true, 100, 110));
+
+        tx.send(test_epoch(2)).unwrap();
+        handle.revoke();
+        assert_eq!(query_status(&client).await, (true, false, 100, 110));
+    }
+
+    #[tokio::test]
+    async fn status_reports_latency_percentiles() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let tracker = LatencyTracker::new();
+        server.latency = Some(tracker.clone());
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let resp = client.status_request().send().promise.await.unwrap();
+        assert_eq!(resp.get().unwrap().get_latency_p50_micros(), 0);
+
+        for ms in 1..=100 {
+            tracker.record(std::time::Duration::from_millis(ms));
+        }
+        let resp = client.status_request().send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_latency_p50_micros() / 1000, 50);
+        assert_eq!(r.get_latency_p99_micros() / 1000, 99);
+
+        negotiate(&client, 10).await;
+        let resp = client.status_request().send().promise.await.unwrap();
+        assert_eq!(resp.get().unwrap().get_latency_p99_micros(), 0);
+    }
+
+    #[tokio::test]
+    async fn simulate_diff_reports_gas_delta() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(103);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_result_a().unwrap().get_gas_used(), 10_500);
+        assert_eq!(r.get_result_b().unwrap().get_gas_used(), 10_300);
+        assert_eq!(r.get_gas_delta(), -200);
+    }
+
+    #[tokio::test]
+    async fn simulate_range_returns_results_in_block_order() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_range_request();
+        req.get().set_from_block(101);
+        req.get().set_to_block(104);
+        let resp = req.send().promise.await.unwrap();
+        let gas: Vec<u64> = resp
+            .get()
+            .unwrap()
+            .get_results()
+            .unwrap()
+            .iter()
+            .map(|r| r.get_gas_used())
+            .collect();
+        assert_eq!(gas, vec![10_100, 10_200, 10_300, 10_400]);
+    }
+
+    #[tokio::test]
+    async fn simulate_range_rejects_bad_ranges() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.block_window = BlockWindowGuard::single(100, 200);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        for (from, to, code) in [
+            (110, 105, "invalidRange"),
+            (100, 116, "rangeTooLarge"),
+            (195, 205, "blockOutOfWindow"),
+        ] {
+            let mut req = client.simulate_range_request();
+            req.get().set_from_block(from);
+            req.get().set_to_block(to);
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.to_string().contains(code), "{}", err);
+        }
+
+        // Exactly the cap is allowed.
+        let mut req = client.simulate_range_request();
+        req.get().set_from_block(100);
+        req.get().set_to_block(115);
+        let resp = req.send().promise.await.unwrap();
+        assert_eq!(resp.get().unwrap().get_results().unwrap().len(), 16);
+    }
+
+    #[tokio::test]
+    async fn simulate_range_spends_only_when_every_block_passes() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(6);
+        server.call_budget = Some(budget.clone());
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        // The last block is out of window: nothing is spent.
+        let mut req = client.simulate_range_request();
+        req.get().set_from_block(108);
+        req.get().set_to_block(111);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+        assert_eq!(budget.remaining(), 6);
+
+        // More blocks than the budget has left: refused whole.
+        let mut req = client.simulate_range_request();
+        req.get().set_from_block(100);
+        req.get().set_to_block(106);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("callBudgetExhausted"));
+        assert_eq!(budget.remaining(), 6);
+
+        let mut req = client.simulate_range_request();
+        req.get().set_from_block(105);
+        req.get().set_to_block(110);
+        req.send().promise.await.unwrap();
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn pinned_state_gives_identical_results_across_targets() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        server.pinned_state = Some(PinnedState {
+            block: 100,
+            hash: None,
+        });
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut gas = Vec::new();
+        for block in [101, 105, 110] {
+            let mut req = client.simulate_request();
+            req.get().set_target_block(block);
+            let resp = req.send().promise.await.unwrap();
+            let r = resp.get().unwrap();
+            assert_eq!(r.get_simulated_block(), block);
+            gas.push(r.get_result().unwrap().get_gas_used());
+        }
+        assert_eq!(gas, vec![10_000; 3]);
+
+        // The window still applies to the requested target.
+        let mut req = client.simulate_request();
+        req.get().set_target_block(111);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+    }
+
+    /// Pays the coinbase 1 gwei per block number.
+    struct BlockPaymentSimulator;
+
+    impl BundleSimulator for BlockPaymentSimulator {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used: 21_000,
+                    success: true,
+                    coinbase_diff: target_block * 1_000_000_000,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    /// Uses no gas at odd blocks and `u64::MAX` at even ones.
+    struct ExtremeGasSimulator;
+
+    impl BundleSimulator for ExtremeGasSimulator {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used: if target_block % 2 == 0 { u64::MAX } else { 0 },
+                    success: true,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn simulate_diff_clamps_gas_delta_beyond_i64() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(ExtremeGasSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        for (block_a, block_b, expected) in [(105, 106, i64::MAX), (106, 105, i64::MIN)] {
+            let mut req = client.simulate_diff_request();
+            req.get().set_block_a(block_a);
+            req.get().set_block_b(block_b);
+            let resp = req.send().promise.await.unwrap();
+            assert_eq!(resp.get().unwrap().get_gas_delta(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn simulate_diff_reports_value_delta() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockPaymentSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(103);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(
+            r.get_result_a().unwrap().get_coinbase_diff(),
+            105_000_000_000
+        );
+        assert_eq!(r.get_gas_delta(), 0);
+        assert_eq!(r.get_value_delta(), -2_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn simulate_diff_with_a_bad_block_costs_nothing() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(5);
+        server.call_budget = Some(budget.clone());
+        let limit = RateLimitGuard::new(0.0, 2);
+        server.rate_limit = Some(limit.clone());
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(200);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+        assert_eq!(budget.remaining(), 5);
+
+        // Both rate-limit tokens are still there for a valid diff.
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(106);
+        req.send().promise.await.unwrap();
+        assert_eq!(budget.remaining(), 3);
+        assert!(limit.take(1).is_err());
+    }
+
+    #[test]
+    fn spend_is_all_or_nothing() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(10);
+        server.call_budget = Some(budget.clone());
+        server.rate_limit = Some(RateLimitGuard::new(0.0, 3));
+
+        let err = server.spend(4).unwrap_err();
+        assert!(err.to_string().contains("rateLimited"));
+        assert_eq!(budget.remaining(), 10, "refused spend is refunded");
+        server.spend(3).unwrap();
+        assert_eq!(budget.remaining(), 7);
+    }
+
+    #[tokio::test]
+    async fn simulate_diff_rejects_out_of_window_block() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(200);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+    }
+
+    #[tokio::test]
+    async fn negotiate_reduces_features_for_old_clients() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.negotiate_request();
+        req.get().set_client_version(1);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_server_version(), SCHEMA_VERSION);
+        let features: Vec<String> = r
+            .get_features()
+            .unwrap()
+            .iter()
+            .map(|f| f.unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(features, vec!["simulate", "include"]);
+
+        // Methods beyond the negotiated version are refused.
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(106);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("featureUnavailable"));
+    }
+
+    async fn negotiate(client: &bundle_capnp::bundle_access::Client, version: u32) {
+        let mut req = client.negotiate_request();
+        req.get().set_client_version(version);
+        req.send().promise.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn old_clients_get_only_the_result_fields_they_know() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(LoggingSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        negotiate(&client, 1).await;
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_simulated_block(), 0);
+        assert!(!r.has_result_hash());
+        let result = r.get_result().unwrap();
+        assert!(result.get_success());
+        assert!(!result.has_tx_results());
+        assert!(!result.has_logs());
+        assert!(!result.has_simulated_by_backend());
+
+        // Version 4 adds per-tx results and backend identity, not logs.
+        negotiate(&client, 4).await;
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_simulated_block(), 105);
+        assert!(!r.has_result_hash());
+        let result = r.get_result().unwrap();
+        assert!(result.has_tx_results());
+        assert!(result.has_simulated_by_backend());
+        assert!(!result.has_logs());
+
+        negotiate(&client, SCHEMA_VERSION).await;
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert!(r.has_result_hash());
+        assert_eq!(r.get_result().unwrap().get_logs().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn negotiate_current_version_gets_full_feature_set() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.negotiate_request();
+        req.get().set_client_version(SCHEMA_VERSION + 5);
+        let resp = req.send().promise.await.unwrap();
+        let features = resp.get().unwrap().get_features().unwrap();
+        assert_eq!(features.len() as usize, features_for(SCHEMA_VERSION).len());
+        assert!(features_for(SCHEMA_VERSION).contains(&"simulateDiff"));
+    }
+
+    #[test]
+    fn success_only_strips_everything_but_success() {
+        let exact = SimResult {
+            gas_used: 90_000,
+            state_root: vec![0xab; 32],
+            revert_reason: "slippage".to_string(),
+            ..Default::default()
+        };
+        let q = ResultQuantization {
+            success_only: true,
+            ..Default::default()
+        };
+        let returned = q.apply(&exact);
+        assert!(!returned.success);
+        assert_eq!(returned.gas_used, 0);
+        assert!(returned.state_root.is_empty());
+        assert!(returned.revert_reason.is_empty());
+        // The server's copy keeps full detail.
+        assert_eq!(exact.gas_used, 90_000);
+        assert_eq!(exact.revert_reason, "slippage");
+    }
+
+    async fn query_is_valid(
+        client: &bundle_capnp::bundle_access::Client,
+        target_block: u64,
+    ) -> (bool, String) {
+        let mut req = client.is_valid_request();
+        req.get().set_target_block(target_block);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        let reason = r.get_reason().unwrap().to_str().unwrap().to_string();
+        (r.get_valid(), reason)
+    }
+
+    #[tokio::test]
+    async fn is_valid_reports_each_guard() {
+        let (tx, rx) = watch::channel(test_epoch(1));
+        let (handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        assert_eq!(query_is_valid(&client, 105).await, (true, String::new()));
+
+        let (valid, reason) = query_is_valid(&client, 200).await;
+        assert!(!valid);
+        assert!(reason.contains("blockOutOfWindow"));
+
+        handle.revoke();
+        let (valid, reason) = query_is_valid(&client, 105).await;
+        assert!(!valid);
+        assert!(reason.contains("revoked"));
+
+        tx.send(test_epoch(2)).unwrap();
+        let (valid, reason) = query_is_valid(&client, 105).await;
+        assert!(!valid);
+        assert!(reason.contains("staleEpoch"));
+    }
+
+    #[test]
+    fn block_window_clamp() {
+        let guard = BlockWindowGuard::single(100, 110);
+        assert_eq!(guard.clamp(50), 100);
+        assert_eq!(guard.clamp(105), 105);
+        assert_eq!(guard.clamp(500), 110);
+    }
+
+    #[test]
+    fn time_window_expires_on_monotonic_deadline() {
+        let guard = TimeWindowGuard::valid_for(std::time::Duration::from_secs(120));
+        assert!(guard.check().is_ok());
+        assert!(guard.check_at(guard.valid_until).is_ok());
+        let later = guard.valid_until + std::time::Duration::from_millis(1);
+        let err = guard.check_at(later).unwrap_err();
+        assert!(err.to_string().contains("grantExpired"));
+    }
+
+    #[tokio::test]
+    async fn expired_grant_rejects_simulate() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.time_window = Some(TimeWindowGuard {
+            valid_until: std::time::Instant::now() - std::time::Duration::from_secs(1),
+        });
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("grantExpired"));
+    }
+
+    #[test]
+    fn block_window_remaining() {
+        let guard = BlockWindowGuard::single(100, 110);
+        assert_eq!(guard.remaining(90), Some(20));
+        assert_eq!(guard.remaining(105), Some(5));
+        assert_eq!(guard.remaining(110), Some(0));
+        assert!(!guard.is_expired(110));
+        assert_eq!(guard.remaining(111), None);
+        assert!(guard.is_expired(111));
+    }
+
+    #[tokio::test]
+    async fn clamp_to_window_reports_adjusted_block() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        server.clamp_to_window = true;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        for (requested, expected) in [(90, 100), (200, 110), (105, 105)] {
+            let mut req = client.simulate_request();
+            req.get().set_target_block(requested);
+            let resp = req.send().promise.await.unwrap();
+            let r = resp.get().unwrap();
+            assert_eq!(r.get_simulated_block(), expected);
+            assert_eq!(r.get_result().unwrap().get_gas_used(), expected * 100);
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_window_rejects_by_default() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(200);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+    }
+
+    #[tokio::test]
+    async fn appended_tx_is_visible_to_next_simulate() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(PerTxGasSimulator);
+        let bundle = server.bundle.clone();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        for (append, expected_gas) in [(false, 21_000), (true, 42_000)] {
+            if append {
+                bundle.append_tx(vec![0x03, 0x04]).unwrap();
+            }
+            let mut req = client.simulate_request();
+            req.get().set_target_block(105);
+            let resp = req.send().promise.await.unwrap();
+            let gas_used = resp.get().unwrap().get_result().unwrap().get_gas_used();
+            assert_eq!(gas_used, expected_gas);
+        }
+    }
+
+    #[tokio::test]
+    async fn simulator_info_reports_backend() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx.clone(), 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let resp = client
+            .simulator_info_request()
+            .send()
+            .promise
+            .await
+            .unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_kind().unwrap().to_str().unwrap(), "revm");
+        assert_eq!(r.get_chain_id(), 1);
+        assert!(r.get_full_evm());
+
+        // Backends that don't describe themselves are not full-EVM.
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let resp = client
+            .simulator_info_request()
+            .send()
+            .promise
+            .await
+            .unwrap();
+        let r = resp.get().unwrap();
+        assert_eq!(r.get_kind().unwrap().to_str().unwrap(), "unknown");
+        assert!(!r.get_full_evm());
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_beyond_cap_are_rejected() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.max_concurrent_calls = Some(2);
+        let active = server.active_calls.clone();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        // Two calls still in progress on this session.
+        let first = active.enter();
+        let _second = active.enter();
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("tooManyConcurrentCalls"));
+
+        // One finishing frees a slot, and the completed call releases it.
+        drop(first);
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        req.send().promise.await.unwrap();
+        assert_eq!(active.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn call_refused_for_concurrency_spends_nothing() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(10);
+        server.call_budget = Some(budget.clone());
+        server.max_concurrent_calls = Some(1);
+        let active = server.active_calls.clone();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let _busy = active.enter();
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("tooManyConcurrentCalls"));
+        let mut req = client.simulate_diff_request();
+        req.get().set_block_a(105);
+        req.get().set_block_b(106);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("tooManyConcurrentCalls"));
+        assert_eq!(budget.remaining(), 10);
+    }
+
+    #[tokio::test]
+    async fn simulation_over_gas_cap_is_reported_failed() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.max_gas = Some(30_000);
+        server.simulate_first = Some(SimulateFirstGuard::default());
+        let bundle = server.bundle.clone();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        assert!(resp.get().unwrap().get_result().unwrap().get_success());
+
+        // A second tx takes the bundle to 42000 gas.
+        bundle.append_tx(vec![0x03]).unwrap();
+        let mut req = client.simulate_request();
+        req.get().set_target_block(106);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        assert!(!r.get_success());
+        assert_eq!(
+            r.get_revert_reason().unwrap().to_str().unwrap(),
+            "gasLimitExceeded"
+        );
+
+        // The capped simulation doesn't unlock include.
+        let err = include(&client, 106).await.unwrap_err();
+        assert!(err.to_string().contains("simulateRequiredFirst"));
+    }
+
+    #[tokio::test]
+    async fn include_waits_for_epoch_to_age() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let (tip_tx, tip) = watch::channel(100);
+        server.epoch_age = Some(EpochAgeGuard {
+            min_age: 3,
+            chain_map: Arc::new(membrane_core::ChainTipMap { tip }),
+        });
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        // test_epoch is adopted at block 100.
+        tip_tx.send(102).unwrap();
+        let err = include(&client, 105).await.unwrap_err();
+        assert!(err.to_string().contains("epochTooFresh"));
+
+        tip_tx.send(103).unwrap();
+        include(&client, 105).await.unwrap();
+    }
+
+    struct HangingSimulator;
+
+    impl BundleSimulator for HangingSimulator {
+        fn simulate(
+            &self,
+            bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            let fut = MockSimulator.simulate(bundle, target_block);
+            Box::pin(async move {
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                fut.await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn hung_backend_times_out_and_frees_its_slot() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(HangingSimulator);
+        server.simulate_timeout = Some(std::time::Duration::from_millis(20));
+        let active = server.active_calls.clone();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("simTimeout"));
+        assert_eq!(active.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn only_allowlisted_fields_are_returned() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.quantization.fields = ResultFields::GAS_USED | ResultFields::REVERT_REASON;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        assert_eq!(r.get_gas_used(), 21000);
+        // MockSimulator reports success and a state root; neither is allowed.
+        assert!(!r.get_success());
+        assert!(r.get_state_root().unwrap().is_empty());
+    }
+
+    /// Routes even blocks to one named backend and odd blocks to another.
+    struct RoutingSimulator;
+
+    impl BundleSimulator for RoutingSimulator {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            let backend = if target_block % 2 == 0 {
+                "node-a"
+            } else {
+                "node-b"
+            };
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used: 21000,
+                    success: true,
+                    simulated_by_backend: backend.to_string(),
+                    simulation_latency_ms: 7,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    async fn query_backend(
+        client: &bundle_capnp::bundle_access::Client,
+        target_block: u64,
+    ) -> (String, u32) {
+        let mut req = client.simulate_request();
+        req.get().set_target_block(target_block);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        (
+            r.get_simulated_by_backend()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+            r.get_simulation_latency_ms(),
+        )
+    }
+
+    #[tokio::test]
+    async fn result_reports_serving_backend_and_latency() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx.clone(), 1);
+        server.simulator = Arc::new(RoutingSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        assert_eq!(query_backend(&client, 104).await, ("node-a".to_string(), 7));
+        assert_eq!(query_backend(&client, 105).await, ("node-b".to_string(), 7));
+
+        // A backend that leaves both unset gets its kind and measured time.
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let (backend, latency_ms) = query_backend(&client, 105).await;
+        assert_eq!(backend, "unknown");
+        assert!(latency_ms < 1_000);
+    }
+
+    #[test]
+    fn blob_tx_requires_type_3_allowed() {
+        let legacy = vec![0xf8, 0x6c];
+        let blob = vec![0x03, 0xf8];
+        let no_blobs: HashSet<u8> = [0, 1, 2].into();
+        let err = BundleSpec::try_new(vec![legacy.clone(), blob.clone()], &no_blobs).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("disallowedTxType: tx 1 has type 3"));
+
+        let with_blobs: HashSet<u8> = [0, 1, 2, 3].into();
+        assert!(BundleSpec::try_new(vec![legacy, blob], &with_blobs).is_ok());
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let limit = RateLimitGuard::new(2.0, 2);
+        let start = tokio::time::Instant::now();
+        assert!(limit.check_at(start).is_ok());
+        assert!(limit.check_at(start).is_ok());
+        let err = limit.check_at(start).unwrap_err();
+        assert!(err.to_string().contains("rateLimited"));
+
+        // Half a second at 2/s refills one token; a clone shares the bucket.
+        let shared = limit.clone();
+        let later = start + std::time::Duration::from_millis(500);
+        assert!(shared.check_at(later).is_ok());
+        assert!(limit.check_at(later).is_err());
+
+        // Refill never exceeds the burst size.
+        let much_later = later + std::time::Duration::from_secs(60);
+        assert!(limit.check_at(much_later).is_ok());
+        assert!(limit.check_at(much_later).is_ok());
+        assert!(limit.check_at(much_later).is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_simulate_is_overloaded() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.rate_limit = Some(RateLimitGuard::new(0.001, 1));
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        req.send().promise.await.unwrap();
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(matches!(err.kind, capnp::ErrorKind::Overloaded));
+        assert!(err.to_string().contains("rateLimited"));
+    }
+
+    #[tokio::test]
+    async fn simulate_and_include_share_call_budget() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(2);
+        server.call_budget = Some(budget.clone());
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        req.send().promise.await.unwrap();
+        assert_eq!(budget.remaining(), 1);
+
+        // isValid observes the budget without spending it.
+        assert_eq!(query_is_valid(&client, 105).await, (true, String::new()));
+        assert_eq!(budget.remaining(), 1);
+
+        let mut req = client.include_request();
+        req.get().set_target_block(105);
+        req.send().promise.await.unwrap();
+        assert_eq!(budget.remaining(), 0);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("callBudgetExhausted"));
+    }
+
+    async fn include(
+        client: &bundle_capnp::bundle_access::Client,
+        block: u64,
+    ) -> Result<(), Error> {
+        let mut req = client.include_request();
+        req.get().set_target_block(block);
+        req.send().promise.await.map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn include_at_a_second_block_is_rejected() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let guard = server.inclusion.clone().unwrap();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        include(&client, 105).await.unwrap();
+        // Idempotent for the same block.
+        include(&client, 105).await.unwrap();
+        let err = include(&client, 106).await.unwrap_err();
+        assert!(err.to_string().contains("alreadyIncluded: at block 105"));
+        assert_eq!(guard.included_at(), Some(105));
+    }
+
+    async fn preflight(client: &bundle_capnp::bundle_access::Client, block: u64) -> (bool, String) {
+        let mut req = client.include_preflight_request();
+        req.get().set_target_block(block);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        let reason = r.get_reason().unwrap().to_str().unwrap().to_string();
+        (r.get_allowed(), reason)
+    }
+
+    #[tokio::test]
+    async fn include_preflight_reports_each_include_guard() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (handle, mut server) = test_server(rx, 1);
+        let simulated = SimulateFirstGuard::default();
+        server.simulate_first = Some(simulated.clone());
+        let (tip_tx, tip) = watch::channel(101);
+        server.epoch_age = Some(EpochAgeGuard {
+            min_age: 3,
+            chain_map: Arc::new(membrane_core::ChainTipMap { tip }),
+        });
+        let inclusion = server.inclusion.clone().unwrap();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let (allowed, reason) = preflight(&client, 105).await;
+        assert!(!allowed);
+        assert!(reason.starts_with("simulateRequiredFirst"));
+
+        simulated.record(105);
+        simulated.record(106);
+        let (allowed, reason) = preflight(&client, 105).await;
+        assert!(!allowed);
+        assert!(reason.starts_with("epochTooFresh"));
+
+        tip_tx.send(103).unwrap();
+        assert_eq!(preflight(&client, 105).await, (true, String::new()));
+        // Preflight does not pin the inclusion.
+        assert_eq!(inclusion.included_at(), None);
+        assert_eq!(preflight(&client, 106).await, (true, String::new()));
+
+        include(&client, 105).await.unwrap();
+        assert_eq!(preflight(&client, 105).await, (true, String::new()));
+        let (allowed, reason) = preflight(&client, 106).await;
+        assert!(!allowed);
+        assert!(reason.starts_with("alreadyIncluded"));
+
+        let (allowed, reason) = preflight(&client, 200).await;
+        assert!(!allowed);
+        assert!(reason.starts_with("blockOutOfWindow"));
+
+        handle.revoke();
+        let (allowed, reason) = preflight(&client, 105).await;
+        assert!(!allowed);
+        assert!(reason.starts_with("revoked"));
+    }
+
+    #[tokio::test]
+    async fn refused_include_costs_nothing() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(5);
+        server.call_budget = Some(budget.clone());
+        server.rate_limit = Some(RateLimitGuard::new(0.0, 2));
+        let simulated = SimulateFirstGuard::default();
+        server.simulate_first = Some(simulated.clone());
+        let (tip_tx, tip) = watch::channel(101);
+        server.epoch_age = Some(EpochAgeGuard {
+            min_age: 3,
+            chain_map: Arc::new(membrane_core::ChainTipMap { tip }),
+        });
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let err = include(&client, 105).await.unwrap_err();
+        assert!(err.to_string().contains("simulateRequiredFirst"));
+        simulated.record(105);
+        simulated.record(106);
+        let err = include(&client, 105).await.unwrap_err();
+        assert!(err.to_string().contains("epochTooFresh"));
+        assert_eq!(budget.remaining(), 5);
+
+        tip_tx.send(103).unwrap();
+        include(&client, 105).await.unwrap();
+        assert_eq!(budget.remaining(), 4);
+
+        let err = include(&client, 106).await.unwrap_err();
+        assert!(err.to_string().contains("alreadyIncluded"));
+        assert_eq!(budget.remaining(), 4);
+        // The second rate-limit token is still there.
+        include(&client, 105).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refused_include_neither_spends_nor_pins() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let budget = CallBudgetGuard::new(10);
+        server.call_budget = Some(budget.clone());
+        let inclusion = server.inclusion.clone().unwrap();
+        inclusion.include(106).unwrap();
+        let err = server.check_include(105).unwrap_err();
+        assert!(err.to_string().contains("alreadyIncluded"));
+        assert_eq!(budget.remaining(), 10);
+
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.rate_limit = Some(RateLimitGuard::new(0.0, 0));
+        let inclusion = server.inclusion.clone().unwrap();
+        let err = server.check_include(105).unwrap_err();
+        assert!(err.to_string().contains("rateLimited"));
+        assert_eq!(inclusion.included_at(), None);
+    }
+
+    #[tokio::test]
+    async fn multi_inclusion_skips_the_check() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.inclusion = None;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        include(&client, 105).await.unwrap();
+        include(&client, 106).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn bundle_update_is_visible_on_next_simulate() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let bundle = server.bundle.clone();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        bundle
+            .update(BundleSpec {
+                txs: vec![vec![0x03], vec![0x04], vec![0x05]],
+            })
+            .unwrap();
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        assert_eq!(r.get_gas_used(), 63_000);
+    }
+
+    #[tokio::test]
+    async fn simulate_reports_one_result_per_tx() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        server.bundle.append_tx(vec![0x03, 0x04]).unwrap();
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        let txs = r.get_tx_results().unwrap();
+        assert_eq!(txs.len(), 2);
+        assert!(txs
+            .iter()
+            .all(|t| t.get_success() && t.get_gas_used() == 21000));
+        assert_eq!(r.get_gas_used(), 42000);
+    }
+
+    struct LoggingSimulator;
+
+    impl BundleSimulator for LoggingSimulator {
+        fn simulate(
+            &self,
+            bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            let fut = MockSimulator.simulate(bundle, target_block);
+            Box::pin(async move {
+                let mut sim = fut.await?;
+                sim.logs = vec![Log {
+                    address: vec![0xaa; 20],
+                    topics: vec![vec![0x01; 32], vec![0x02; 32]],
+                    data: vec![0x03; 64],
+                }];
+                Ok(sim)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn simulate_returns_logs() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(LoggingSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let logs = resp
+            .get()
+            .unwrap()
+            .get_result()
+            .unwrap()
+            .get_logs()
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+        let log = logs.get(0);
+        assert_eq!(log.get_address().unwrap(), &[0xaa; 20][..]);
+        let topics = log.get_topics().unwrap();
+        assert_eq!(topics.len(), 2);
+        assert_eq!(topics.get(1).unwrap(), &[0x02; 32][..]);
+        assert_eq!(log.get_data().unwrap(), &[0x03; 64][..]);
+    }
+
+    #[tokio::test]
+    async fn empty_and_withheld_logs_serialize_as_empty_list() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx.clone(), 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        assert_eq!(r.get_logs().unwrap().len(), 0);
+
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(LoggingSimulator);
+        server.quantization.fields = ResultFields::GAS_USED | ResultFields::SUCCESS;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap().get_result().unwrap();
+        assert_eq!(r.get_logs().unwrap().len(), 0);
+        assert!(r.get_success());
+    }
+
+    #[test]
+    fn tx_results_follow_field_allowlist() {
+        let exact = SimResult {
+            gas_used: 50_000,
+            revert_reason: "tx 1 reverted".to_string(),
+            tx_results: vec![
+                TxResult {
+                    gas_used: 21_000,
+                    success: true,
+                    revert_reason: String::new(),
+                },
+                TxResult {
+                    gas_used: 29_000,
+                    success: false,
+                    revert_reason: "slippage".to_string(),
+                },
+            ],
+            ..Default::default()
+        };
+        let q = ResultQuantization {
+            fields: ResultFields::TX_RESULTS | ResultFields::SUCCESS,
+            ..Default::default()
+        };
+        let returned = q.apply(&exact);
+        let outcomes: Vec<_> = returned
+            .tx_results
+            .iter()
+            .map(|t| (t.gas_used, t.success, t.revert_reason.as_str()))
+            .collect();
+        assert_eq!(outcomes, vec![(0, true, ""), (0, false, "")]);
+
+        let q = ResultQuantization {
+            success_only: true,
+            ..Default::default()
+        };
+        assert!(q.apply(&exact).tx_results.is_empty());
+    }
+
+    async fn resimulate_and_diff(
+        client: &bundle_capnp::bundle_access::Client,
+        prior: &[u8],
+        target_block: u64,
+    ) -> Result<Vec<String>, Error> {
+        let mut req = client.resimulate_and_diff_request();
+        req.get().set_prior_result_hash(prior);
+        req.get().set_target_block(target_block);
+        let resp = req.send().promise.await?;
+        let changed = resp.get()?.get_changed_fields()?;
+        Ok(changed
+            .iter()
+            .map(|f| f.unwrap().to_str().unwrap().to_string())
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn resimulate_and_diff_reports_changed_fields() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let prior = resp.get().unwrap().get_result_hash().unwrap().to_vec();
+        assert_eq!(prior.len(), 32);
+
+        // Same environment: nothing changed.
+        let changed = resimulate_and_diff(&client, &prior, 105).await.unwrap();
+        assert!(changed.is_empty());
+
+        // BlockGasSimulator's gas depends on the block.
+        let changed = resimulate_and_diff(&client, &prior, 106).await.unwrap();
+        assert_eq!(changed, vec!["gasUsed".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn resimulate_and_diff_rejects_unknown_hash() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx, 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let err = resimulate_and_diff(&client, &[0u8; 32], 105)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unknownResult"));
+    }
+
+    #[tokio::test]
+    async fn resimulate_and_diff_with_unknown_hash_costs_nothing() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.call_budget = Some(CallBudgetGuard::new(1));
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        assert!(resimulate_and_diff(&client, &[0u8; 32], 105).await.is_err());
+
+        let mut req = client.simulate_request();
+        req.get().set_target_block(105);
+        req.send().promise.await.unwrap();
+    }
+
+    #[test]
+    fn recent_results_evicts_oldest() {
+        let recent = RecentResults::default();
+        let sim = |gas_used| SimResult {
+            gas_used,
+            success: true,
+            ..Default::default()
+        };
+        let first = recent.record(&sim(0));
+        for gas in 1..=RECENT_RESULTS_CAPACITY as u64 {
+            recent.record(&sim(gas));
+        }
+        assert!(recent.get(&first).is_none());
+        let last = sim(RECENT_RESULTS_CAPACITY as u64).hash();
+        assert_eq!(
+            recent.get(&last).unwrap().gas_used,
+            RECENT_RESULTS_CAPACITY as u64
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<AuditEvent>>);
+
+    impl AuditSink for RecordingSink {
+        fn record(&self, event: &AuditEvent) -> Result<(), Error> {
+            self.0.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    async fn sampled_results(sampling_rate: f64) -> Vec<AuditEvent> {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let sink = Arc::new(RecordingSink::default());
+        server.audit = Some(sink.clone());
+        server.audit_sampler = Some(AuditSampler::with_seed(sampling_rate, 1));
+        server.quantization.gas_bucket = 100_000;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        for block in 101..=105 {
+            let mut req = client.simulate_request();
+            req.get().set_target_block(block);
+            req.send().promise.await.unwrap();
+        }
+        let events = sink.0.lock().unwrap().clone();
+        events
+            .into_iter()
+            .filter(|e| matches!(e, AuditEvent::SimulationSample { .. }))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn full_sampling_persists_every_exact_result() {
+        let samples = sampled_results(1.0).await;
+        assert_eq!(samples.len(), 5);
+        let AuditEvent::SimulationSample {
+            bundle_hash,
+            target_block,
+            result,
+        } = &samples[0]
+        else {
+            unreachable!()
+        };
+        assert_eq!(*target_block, 101);
+        assert_eq!(
+            *bundle_hash,
+            BundleSpec {
+                txs: vec![vec![0x01, 0x02]],
+            }
+            .hash()
+        );
+        // The sample is unquantized even though the builder's view is.
+        assert_eq!(result.gas_used, 21_000);
+    }
+
+    #[tokio::test]
+    async fn zero_sampling_persists_nothing() {
+        assert!(sampled_results(0.0).await.is_empty());
+    }
+
+    async fn query_trace(
+        client: &bundle_capnp::bundle_access::Client,
+        target_block: u64,
+    ) -> Result<Vec<u8>, Error> {
+        let mut req = client.trace_request();
+        req.get().set_target_block(target_block);
+        let resp = req.send().promise.await?;
+        Ok(resp.get()?.get_frames()?.to_vec())
+    }
+
+    #[tokio::test]
+    async fn trace_returns_backend_frames() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        assert_eq!(query_trace(&client, 105).await.unwrap(), b"{\"block\":105}");
+
+        let err = query_trace(&client, 200).await.unwrap_err();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+    }
+
+    #[tokio::test]
+    async fn trace_is_unimplemented_without_backend_support_or_disclosure() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, server) = test_server(rx.clone(), 1);
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let err = query_trace(&client, 105).await.unwrap_err();
+        assert!(matches!(err.kind, capnp::ErrorKind::Unimplemented));
+        assert!(err.to_string().contains("traceUnsupported"));
+
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(BlockGasSimulator);
+        server.quantization.success_only = true;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+        let err = query_trace(&client, 105).await.unwrap_err();
+        assert!(matches!(err.kind, capnp::ErrorKind::Unimplemented));
+        assert!(err.to_string().contains("traceUnavailable"));
+    }
+
+    #[tokio::test]
+    async fn trace_is_unavailable_under_bucketing() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let buckets = [
+            ResultQuantization {
+                gas_bucket: 1_000,
+                ..ResultQuantization::default()
+            },
+            ResultQuantization {
+                coinbase_bucket: 1_000,
+                ..ResultQuantization::default()
+            },
+        ];
+        for quantization in buckets {
+            let (_handle, mut server) = test_server(rx.clone(), 1);
+            server.simulator = Arc::new(BlockGasSimulator);
+            server.quantization = quantization;
+            let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+            let err = query_trace(&client, 105).await.unwrap_err();
+            assert!(err.to_string().contains("traceUnavailable"));
+        }
+    }
+
+    struct LargeTraceSimulator;
+
+    impl BundleSimulator for LargeTraceSimulator {
+        fn simulate(
+            &self,
+            bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            MockSimulator.simulate(bundle, target_block)
+        }
+
+        fn trace(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<u8>, Error>> + Send>>
+        {
+            // Small near the window start, large after.
+            let calls = if target_block < 105 { 1 } else { 2000 };
+            let body = vec!["{\"type\":\"CALL\"}"; calls].join(",");
+            let frames = format!("[{}]", body).into_bytes();
+            Box::pin(async move { Ok(frames) })
+        }
+    }
+
+    async fn query_compressed_trace(
+        client: &bundle_capnp::bundle_access::Client,
+        target_block: u64,
+    ) -> (Vec<u8>, bool) {
+        let mut req = client.trace_request();
+        req.get().set_target_block(target_block);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        (r.get_frames().unwrap().to_vec(), r.get_compressed())
+    }
+
+    #[tokio::test]
+    async fn large_traces_round_trip_through_compression() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        server.simulator = Arc::new(LargeTraceSimulator);
+        server.compress_traces = true;
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        let expected = LargeTraceSimulator
+            .trace(&BundleSpec { txs: vec![] }, 106)
+            .await
+            .unwrap();
+        let (frames, compressed) = query_compressed_trace(&client, 106).await;
+        assert!(compressed);
+        assert!(frames.len() < expected.len());
+        assert_eq!(decompress_trace(&frames, compressed).unwrap(), expected);
+
+        // Small traces skip compression.
+        let (frames, compressed) = query_compressed_trace(&client, 101).await;
+        assert!(!compressed);
+        assert_eq!(frames, b"[{\"type\":\"CALL\"}]");
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver(Mutex<Vec<(String, u64, bool)>>);
+
+    impl GuardObserver for RecordingObserver {
+        fn on_check(&self, method: &str, target_block: u64, outcome: &Result<(), Error>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((method.to_string(), target_block, outcome.is_ok()));
+        }
+    }
+
+    #[tokio::test]
+    async fn guard_observer_sees_allow_and_deny() {
+        let (_tx, rx) = watch::channel(test_epoch(1));
+        let (_handle, mut server) = test_server(rx, 1);
+        let observer = Arc::new(RecordingObserver::default());
+        server.guard_observer = Some(observer.clone());
+        let client: bundle_capnp::bundle_access::Client = capnp_rpc::new_client(server);
+
+        for target_block in [105, 200] {
+            let mut req = client.simulate_request();
+            req.get().set_target_block(target_block);
+            let _ = req.send().promise.await;
+        }
+        query_is_valid(&client, 106).await;
+
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            vec![
+                ("simulate".to_string(), 105, true),
+                ("simulate".to_string(), 200, false),
+                ("isValid".to_string(), 106, true),
+            ]
+        );
     }
 }
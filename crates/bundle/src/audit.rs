@@ -0,0 +1,318 @@
+//! Audit log for grant lifecycle events.
+//!
+//! [`AuditSink`] receives every grant issuance, simulate, include and revoke.
+//! An [`AuditSampler`] additionally persists the full result of a random
+//! fraction of simulations, for spot-checking the simulator later.
+//! [`AuditTrail`] is a file-backed sink that writes a hash-chained,
+//! append-only log so the record can be verified after the fact.
+//!
+//! Each line has the form `<prev> <hash> <event>`, where `prev` is the hex
+//! hash of the preceding record (all zeros for the first), and
+//! `hash = keccak256(prev || event)`. Editing, dropping or reordering any
+//! record breaks the chain from that point on.
+
+use crate::access::SimResult;
+use crate::pubkey::keccak256;
+use capnp::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A grant lifecycle event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditEvent {
+    GrantIssued {
+        builder_pubkey: Vec<u8>,
+        valid_from: u64,
+        valid_until: u64,
+    },
+    Simulate {
+        target_block: u64,
+        success: bool,
+    },
+    Include {
+        target_block: u64,
+    },
+    Revoke,
+    /// Full, unquantized result of a sampled simulation. Server-side only.
+    SimulationSample {
+        bundle_hash: [u8; 32],
+        target_block: u64,
+        result: SimResult,
+    },
+}
+
+impl AuditEvent {
+    /// Single-line text encoding used as the record body.
+    pub fn encode(&self) -> String {
+        match self {
+            Self::GrantIssued {
+                builder_pubkey,
+                valid_from,
+                valid_until,
+            } => format!(
+                "grantIssued builder={} from={} until={}",
+                to_hex(builder_pubkey),
+                valid_from,
+                valid_until
+            ),
+            Self::Simulate {
+                target_block,
+                success,
+            } => format!("simulate block={} success={}", target_block, success),
+            Self::Include { target_block } => format!("include block={}", target_block),
+            Self::Revoke => "revoke".to_string(),
+            Self::SimulationSample {
+                bundle_hash,
+                target_block,
+                result,
+            } => format!(
+                "simulationSample bundle={} block={} gas={} success={} coinbaseDiff={} \
+                 stateRoot={} backend={:?} latencyMs={} revert={:?} txs={:?} logs={:?}",
+                to_hex(bundle_hash),
+                target_block,
+                result.gas_used,
+                result.success,
+                result.coinbase_diff,
+                to_hex(&result.state_root),
+                result.simulated_by_backend,
+                result.simulation_latency_ms,
+                result.revert_reason,
+                result.tx_results,
+                result.logs
+            ),
+        }
+    }
+}
+
+/// Destination for audit events.
+pub trait AuditSink: Send + Sync + 'static {
+    fn record(&self, event: &AuditEvent) -> Result<(), Error>;
+}
+
+/// Record an event to the audit sink, if any. Sink failures are logged
+/// rather than failing the call.
+pub(crate) fn record_audit(sink: &Option<Arc<dyn AuditSink>>, event: AuditEvent) {
+    if let Some(sink) = sink {
+        if let Err(e) = sink.record(&event) {
+            tracing::warn!("audit record failed: {}", e);
+        }
+    }
+}
+
+/// Decides which simulations are persisted in full to the audit sink.
+///
+/// Each simulation is sampled independently with probability
+/// `sampling_rate`. Clones share one random stream, so a sampler set on a
+/// grant covers all of its sessions. The stream is a seeded SplitMix64:
+/// not cryptographic, but a builder never sees which results were kept.
+#[derive(Clone, Debug)]
+pub struct AuditSampler {
+    pub sampling_rate: f64,
+    state: Arc<Mutex<u64>>,
+}
+
+impl AuditSampler {
+    /// Sample with probability `sampling_rate`, seeded from the clock.
+    pub fn new(sampling_rate: f64) -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::with_seed(sampling_rate, seed)
+    }
+
+    /// Sample with probability `sampling_rate` from a fixed seed, for
+    /// reproducible sampling.
+    pub fn with_seed(sampling_rate: f64, seed: u64) -> Self {
+        Self {
+            sampling_rate,
+            state: Arc::new(Mutex::new(seed)),
+        }
+    }
+
+    /// Whether to persist the next simulation.
+    pub fn sample(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // Uniform in [0, 1): rate 1.0 always samples, 0.0 never does.
+        let unit = (z >> 11) as f64 / (1u64 << 53) as f64;
+        unit < self.sampling_rate
+    }
+}
+
+/// Hash-chained append-only audit log backed by a file.
+pub struct AuditTrail {
+    path: PathBuf,
+    state: Mutex<(File, [u8; 32])>,
+}
+
+impl AuditTrail {
+    /// Open (or create) the log at `path`. An existing log is verified and
+    /// new records are chained onto its last hash.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let last = if path.exists() {
+            verify_chain(&path)?
+        } else {
+            [0u8; 32]
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| Error::failed(format!("auditIo: {}", e)))?;
+        Ok(Self {
+            path,
+            state: Mutex::new((file, last)),
+        })
+    }
+
+    /// Verify the chain of this trail's file.
+    pub fn verify(&self) -> Result<(), Error> {
+        verify_chain(&self.path).map(|_| ())
+    }
+}
+
+impl AuditSink for AuditTrail {
+    fn record(&self, event: &AuditEvent) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let (file, prev) = &mut *state;
+        let body = event.encode();
+        let hash = chain_hash(prev, &body);
+        writeln!(file, "{} {} {}", to_hex(prev), to_hex(&hash), body)
+            .and_then(|_| file.flush())
+            .map_err(|e| Error::failed(format!("auditIo: {}", e)))?;
+        *prev = hash;
+        Ok(())
+    }
+}
+
+/// Verify a hash-chained log file, returning the hash of its last record.
+pub fn verify_chain(path: impl AsRef<Path>) -> Result<[u8; 32], Error> {
+    let file = File::open(path).map_err(|e| Error::failed(format!("auditIo: {}", e)))?;
+    let mut prev = [0u8; 32];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| Error::failed(format!("auditIo: {}", e)))?;
+        let mut parts = line.splitn(3, ' ');
+        let (Some(prev_hex), Some(hash_hex), Some(body)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Error::failed(format!(
+                "auditTampered: malformed record {}",
+                i
+            )));
+        };
+        if prev_hex != to_hex(&prev) {
+            return Err(Error::failed(format!(
+                "auditTampered: broken link at record {}",
+                i
+            )));
+        }
+        let hash = chain_hash(&prev, body);
+        if hash_hex != to_hex(&hash) {
+            return Err(Error::failed(format!(
+                "auditTampered: hash mismatch at record {}",
+                i
+            )));
+        }
+        prev = hash;
+    }
+    Ok(prev)
+}
+
+fn chain_hash(prev: &[u8; 32], body: &str) -> [u8; 32] {
+    let mut buf = Vec::with_capacity(32 + body.len());
+    buf.extend_from_slice(prev);
+    buf.extend_from_slice(body.as_bytes());
+    keccak256(&buf)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "membrane-audit-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn write_sample(path: &Path) {
+        let trail = AuditTrail::open(path).unwrap();
+        trail
+            .record(&AuditEvent::GrantIssued {
+                builder_pubkey: vec![0x02; 33],
+                valid_from: 100,
+                valid_until: 110,
+            })
+            .unwrap();
+        trail
+            .record(&AuditEvent::Simulate {
+                target_block: 105,
+                success: true,
+            })
+            .unwrap();
+        trail
+            .record(&AuditEvent::Include { target_block: 105 })
+            .unwrap();
+        trail.record(&AuditEvent::Revoke).unwrap();
+    }
+
+    #[test]
+    fn sampler_rate_bounds() {
+        let always = AuditSampler::with_seed(1.0, 7);
+        let never = AuditSampler::with_seed(0.0, 7);
+        assert!((0..1000).all(|_| always.sample()));
+        assert!((0..1000).all(|_| !never.sample()));
+    }
+
+    #[test]
+    fn sampler_is_reproducible_from_seed() {
+        let a = AuditSampler::with_seed(0.5, 42);
+        let b = AuditSampler::with_seed(0.5, 42);
+        let draws: Vec<bool> = (0..64).map(|_| a.sample()).collect();
+        assert_eq!(draws, (0..64).map(|_| b.sample()).collect::<Vec<_>>());
+        assert!(draws.contains(&true) && draws.contains(&false));
+    }
+
+    #[test]
+    fn chain_verifies_end_to_end() {
+        let path = temp_log("verify");
+        write_sample(&path);
+        assert!(verify_chain(&path).is_ok());
+
+        // Reopening continues the chain.
+        let trail = AuditTrail::open(&path).unwrap();
+        trail.record(&AuditEvent::Revoke).unwrap();
+        assert!(trail.verify().is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tampered_middle_record_is_detected() {
+        let path = temp_log("tamper");
+        write_sample(&path);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let tampered = content.replace("simulate block=105", "simulate block=106");
+        assert_ne!(content, tampered);
+        std::fs::write(&path, tampered).unwrap();
+
+        let err = verify_chain(&path).unwrap_err();
+        assert!(err.to_string().contains("auditTampered"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+//! Message authentication for grants and results.
+//!
+//! [`Authenticator`] abstracts over how an attestation is produced and
+//! checked. Two implementations are provided:
+//!
+//! - [`SignatureAuthenticator`] — recoverable secp256k1 signatures over the
+//!   digest of the message, keccak256 by default (see [`HashAlgo`]).
+//!   Verifiable by anyone holding the signer's public key or address.
+//! - [`HmacAuthenticator`] — HMAC-SHA256 with a key pre-shared between
+//!   searcher and builder. Much cheaper; only suitable for trusted networks.
+//!
+//! [`KeySigner`] serves the builder's side of graft-time authentication:
+//! it answers the membrane's `Signer.sign` challenge with a recoverable
+//! signature over [`signer_message`]. The membrane draws each challenge's
+//! nonce from [`ChallengeNonces`], so every graft signs something new.
+
+use crate::error::MembraneError;
+use crate::pubkey::{keccak256, BuilderKey};
+use capnp::capability::Promise;
+use capnp::Error;
+use capnp_rpc::pry;
+use hmac::{Hmac, Mac};
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+use k256::PublicKey;
+use membrane_core::stem_capnp;
+use sha2::{Digest, Sha256};
+
+/// Produces and checks authentication tags over messages.
+pub trait Authenticator: Send + Sync + 'static {
+    /// Authenticate `message`, returning its tag.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Check `tag` against `message`.
+    fn verify(&self, message: &[u8], tag: &[u8]) -> Result<(), Error>;
+}
+
+/// Digest a message is hashed with before it is signed.
+///
+/// Signer and verifier must agree: a signature made under one algorithm
+/// recovers to an unrelated key under the other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgo {
+    /// Ethereum's keccak256.
+    #[default]
+    Keccak256,
+    /// SHA-256, for verification tooling outside Ethereum.
+    Sha256,
+}
+
+impl HashAlgo {
+    pub fn digest(self, message: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgo::Keccak256 => keccak256(message),
+            HashAlgo::Sha256 => Sha256::digest(message).into(),
+        }
+    }
+}
+
+/// secp256k1 signatures: 65 bytes, `r || s || v` with `v` in `{0, 1}`.
+pub struct SignatureAuthenticator {
+    signing_key: Option<SigningKey>,
+    expected: BuilderKey,
+    hash: HashAlgo,
+}
+
+impl SignatureAuthenticator {
+    /// Sign with `signing_key`; verify against its own public key.
+    pub fn signer(signing_key: SigningKey) -> Self {
+        let expected = BuilderKey::PublicKey(PublicKey::from(signing_key.verifying_key()));
+        Self {
+            signing_key: Some(signing_key),
+            expected,
+            hash: HashAlgo::default(),
+        }
+    }
+
+    /// Verify-only: accept signatures recovering to `expected`.
+    pub fn verifier(expected: BuilderKey) -> Self {
+        Self {
+            signing_key: None,
+            expected,
+            hash: HashAlgo::default(),
+        }
+    }
+
+    /// Hash messages with `hash` instead of keccak256.
+    pub fn with_hash(mut self, hash: HashAlgo) -> Self {
+        self.hash = hash;
+        self
+    }
+}
+
+impl Authenticator for SignatureAuthenticator {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = self
+            .signing_key
+            .as_ref()
+            .ok_or_else(|| Error::failed("authFailed: no signing key".to_string()))?;
+        let (sig, recid) = key
+            .sign_prehash_recoverable(&self.hash.digest(message))
+            .map_err(|e| Error::failed(format!("authFailed: {}", e)))?;
+        let mut out = sig.to_bytes().to_vec();
+        out.push(recid.to_byte());
+        Ok(out)
+    }
+
+    fn verify(&self, message: &[u8], tag: &[u8]) -> Result<(), Error> {
+        let signer = recover_signer_with(self.hash, message, tag)?;
+        if signer != self.expected {
+            return Err(Error::failed(
+                "authFailed: signature does not match expected key".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Recover the signer of a 65-byte recoverable signature over the
+/// keccak256 digest of `message`.
+pub fn recover_signer(message: &[u8], sig: &[u8]) -> Result<BuilderKey, Error> {
+    recover_signer_with(HashAlgo::Keccak256, message, sig)
+}
+
+/// [`recover_signer`] for a signature over `hash`'s digest of `message`.
+///
+/// `v` may be a raw recovery id (`0`/`1`) or Ethereum's `27`/`28`; any other
+/// value is rejected rather than reduced.
+pub fn recover_signer_with(
+    hash: HashAlgo,
+    message: &[u8],
+    sig: &[u8],
+) -> Result<BuilderKey, Error> {
+    if sig.len() != 65 {
+        return Err(Error::failed(format!(
+            "authFailed: signature must be 65 bytes, got {}",
+            sig.len()
+        )));
+    }
+    let signature = Signature::from_slice(&sig[..64])
+        .map_err(|_| Error::failed("authFailed: malformed signature".to_string()))?;
+    let recid = match sig[64] {
+        0 | 27 => RecoveryId::from_byte(0),
+        1 | 28 => RecoveryId::from_byte(1),
+        _ => None,
+    }
+    .ok_or_else(|| Error::failed(format!("authFailed: bad recovery id {}", sig[64])))?;
+    let vk = VerifyingKey::recover_from_prehash(&hash.digest(message), &signature, recid)
+        .map_err(|_| Error::failed("authFailed: signature recovery failed".to_string()))?;
+    Ok(BuilderKey::PublicKey(PublicKey::from(&vk)))
+}
+
+/// The bytes attested for a grant: a fixed domain, the builder key as
+/// stored in the grant, then the big-endian window bounds.
+pub fn grant_message(builder_pubkey: &[u8], valid_from: u64, valid_until: u64) -> Vec<u8> {
+    let mut msg = b"membrane-bundle/grant/".to_vec();
+    msg.extend_from_slice(builder_pubkey);
+    msg.extend_from_slice(&valid_from.to_be_bytes());
+    msg.extend_from_slice(&valid_until.to_be_bytes());
+    msg
+}
+
+/// The bytes a `Signer` signs for `sign(domain, nonce)`: the UTF-8 domain
+/// followed by the big-endian nonce.
+pub fn signer_message(domain: &str, nonce: u64) -> Vec<u8> {
+    let mut msg = domain.as_bytes().to_vec();
+    msg.extend_from_slice(&nonce.to_be_bytes());
+    msg
+}
+
+/// Nonces for builder-auth challenges. Each is 64 random bits, so a
+/// signature captured from one graft matches another's challenge with
+/// negligible probability. Nothing is stored, so unauthenticated grafts
+/// can't grow server memory.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChallengeNonces;
+
+impl ChallengeNonces {
+    /// A random nonce.
+    pub fn fresh(&self) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        getrandom::getrandom(&mut buf).map_err(|e| MembraneError::BuilderAuthFailed {
+            detail: format!("no randomness: {}", e),
+        })?;
+        Ok(u64::from_be_bytes(buf))
+    }
+}
+
+/// `Signer` capability backed by a local secp256k1 key, for builders
+/// proving key ownership during `graft`.
+pub struct KeySigner {
+    auth: SignatureAuthenticator,
+}
+
+impl KeySigner {
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self {
+            auth: SignatureAuthenticator::signer(signing_key),
+        }
+    }
+}
+
+#[allow(refining_impl_trait)]
+impl stem_capnp::signer::Server for KeySigner {
+    fn sign(
+        self: capnp::capability::Rc<Self>,
+        params: stem_capnp::signer::SignParams,
+        mut results: stem_capnp::signer::SignResults,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let domain = pry!(pry!(params.get_domain()).to_str());
+        let msg = signer_message(domain, params.get_nonce());
+        let sig = pry!(self.auth.sign(&msg));
+        results.get().set_sig(&sig);
+        Promise::ok(())
+    }
+}
+
+/// HMAC-SHA256 with a pre-shared key.
+pub struct HmacAuthenticator {
+    key: Vec<u8>,
+}
+
+impl HmacAuthenticator {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+}
+
+impl Authenticator for HmacAuthenticator {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut mac = self.mac();
+        mac.update(message);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+
+    fn verify(&self, message: &[u8], tag: &[u8]) -> Result<(), Error> {
+        let mut mac = self.mac();
+        mac.update(message);
+        mac.verify_slice(tag)
+            .map_err(|_| Error::failed("authFailed: MAC mismatch".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRANT: &[u8] = b"grant: builder=0x02.. from=100 until=110";
+
+    #[test]
+    fn hmac_verifies_with_shared_key_only() {
+        let searcher = HmacAuthenticator::new(b"shared secret".to_vec());
+        let builder = HmacAuthenticator::new(b"shared secret".to_vec());
+        let intruder = HmacAuthenticator::new(b"wrong secret".to_vec());
+
+        let tag = searcher.sign(GRANT).unwrap();
+        assert!(builder.verify(GRANT, &tag).is_ok());
+        assert!(builder.verify(b"tampered", &tag).is_err());
+        let err = intruder.verify(GRANT, &tag).unwrap_err();
+        assert!(err.to_string().contains("authFailed"));
+    }
+
+    #[test]
+    fn signature_verifies_against_key_or_address() {
+        let sk = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let pk = PublicKey::from(sk.verifying_key());
+        let signer = SignatureAuthenticator::signer(sk);
+        let tag = signer.sign(GRANT).unwrap();
+        assert_eq!(tag.len(), 65);
+
+        let by_key = SignatureAuthenticator::verifier(BuilderKey::PublicKey(pk));
+        let by_addr = SignatureAuthenticator::verifier(BuilderKey::Address(
+            BuilderKey::PublicKey(pk).address(),
+        ));
+        assert!(by_key.verify(GRANT, &tag).is_ok());
+        assert!(by_addr.verify(GRANT, &tag).is_ok());
+        assert!(by_key.verify(b"tampered", &tag).is_err());
+
+        let other = SigningKey::from_slice(&[0x33; 32]).unwrap();
+        let wrong = SignatureAuthenticator::verifier(BuilderKey::PublicKey(PublicKey::from(
+            other.verifying_key(),
+        )));
+        assert!(wrong.verify(GRANT, &tag).is_err());
+    }
+
+    #[test]
+    fn signatures_verify_only_under_the_signing_algorithm() {
+        let sk = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let pk = BuilderKey::PublicKey(PublicKey::from(sk.verifying_key()));
+        for (algo, other) in [
+            (HashAlgo::Keccak256, HashAlgo::Sha256),
+            (HashAlgo::Sha256, HashAlgo::Keccak256),
+        ] {
+            let signer = SignatureAuthenticator::signer(sk.clone()).with_hash(algo);
+            let tag = signer.sign(GRANT).unwrap();
+            assert!(signer.verify(GRANT, &tag).is_ok());
+
+            let same = SignatureAuthenticator::verifier(pk.clone()).with_hash(algo);
+            assert!(same.verify(GRANT, &tag).is_ok());
+            assert_eq!(recover_signer_with(algo, GRANT, &tag).unwrap(), pk);
+
+            let cross = SignatureAuthenticator::verifier(pk.clone()).with_hash(other);
+            let err = cross.verify(GRANT, &tag).unwrap_err();
+            assert!(err.to_string().contains("authFailed"));
+        }
+    }
+
+    #[test]
+    fn only_canonical_recovery_ids_are_accepted() {
+        let sk = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let pk = BuilderKey::PublicKey(PublicKey::from(sk.verifying_key()));
+        let mut tag = SignatureAuthenticator::signer(sk).sign(GRANT).unwrap();
+        let recid = tag[64];
+        assert!(recid <= 1);
+
+        tag[64] = recid + 27;
+        assert_eq!(recover_signer(GRANT, &tag).unwrap(), pk);
+
+        for v in [2, 3, 26, 29, 30, 54, 55, 37, 38] {
+            tag[64] = v;
+            let err = recover_signer(GRANT, &tag).unwrap_err();
+            assert!(err.to_string().contains("bad recovery id"), "v = {}", v);
+        }
+    }
+
+    #[test]
+    fn keccak256_is_the_default() {
+        let sk = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let tag = SignatureAuthenticator::signer(sk.clone())
+            .sign(GRANT)
+            .unwrap();
+        let explicit = SignatureAuthenticator::signer(sk)
+            .with_hash(HashAlgo::Keccak256)
+            .sign(GRANT)
+            .unwrap();
+        assert_eq!(tag, explicit);
+        assert!(recover_signer(GRANT, &tag).is_ok());
+    }
+}
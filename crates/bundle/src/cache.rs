@@ -0,0 +1,211 @@
+//! Per-grant simulation result cache.
+//!
+//! [`CachingSimulator`] wraps a backend so a builder's repeated identical
+//! simulations are served from memory. Each grant gets its own cache, so
+//! results never leak across grants, and the cache is emptied as soon as
+//! the grant is revoked.
+
+use crate::access::{BundleSimulator, BundleSpec, PinnedState, SimResult, SimulatorInfo};
+use crate::revocation::RevocationGuard;
+use capnp::Error;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Cache key: target block and bundle contents hash.
+type CacheKey = (u64, [u8; 32]);
+
+#[derive(Default)]
+struct Entries {
+    results: HashMap<CacheKey, SimResult>,
+    /// Insertion order, for FIFO eviction once `capacity` is reached.
+    order: VecDeque<CacheKey>,
+}
+
+/// Bounded result cache in front of a [`BundleSimulator`].
+pub struct CachingSimulator {
+    inner: Arc<dyn BundleSimulator>,
+    capacity: usize,
+    entries: Arc<Mutex<Entries>>,
+    revocation_guard: RevocationGuard,
+}
+
+impl CachingSimulator {
+    /// Cache up to `capacity` results from `inner`, cleared when
+    /// `revocation_guard`'s grant is revoked.
+    pub fn new(
+        inner: Arc<dyn BundleSimulator>,
+        capacity: usize,
+        revocation_guard: &RevocationGuard,
+    ) -> Self {
+        let entries: Arc<Mutex<Entries>> = Arc::default();
+        let weak = Arc::downgrade(&entries);
+        revocation_guard.on_revoke(move || {
+            if let Some(entries) = weak.upgrade() {
+                *entries.lock().unwrap() = Entries::default();
+            }
+        });
+        Self {
+            inner,
+            capacity,
+            entries,
+            revocation_guard: revocation_guard.clone(),
+        }
+    }
+
+    /// Number of cached results.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl BundleSimulator for CachingSimulator {
+    fn simulate(
+        &self,
+        bundle: &BundleSpec,
+        target_block: u64,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>> {
+        let key = (target_block, bundle.hash());
+        if let Some(hit) = self.entries.lock().unwrap().results.get(&key).cloned() {
+            return Box::pin(async move { Ok(hit) });
+        }
+
+        let fut = self.inner.simulate(bundle, target_block);
+        let entries = self.entries.clone();
+        let capacity = self.capacity;
+        let revocation_guard = self.revocation_guard.clone();
+        Box::pin(async move {
+            let sim = fut.await?;
+            // A simulation that finishes after revocation is not cached.
+            if capacity > 0 && revocation_guard.check().is_ok() {
+                let mut e = entries.lock().unwrap();
+                if e.results.len() >= capacity {
+                    if let Some(oldest) = e.order.pop_front() {
+                        e.results.remove(&oldest);
+                    }
+                }
+                if e.results.insert(key, sim.clone()).is_none() {
+                    e.order.push_back(key);
+                }
+            }
+            Ok(sim)
+        })
+    }
+
+    fn info(&self) -> SimulatorInfo {
+        self.inner.info()
+    }
+
+    fn trace(
+        &self,
+        bundle: &BundleSpec,
+        target_block: u64,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<u8>, Error>> + Send>> {
+        self.inner.trace(bundle, target_block)
+    }
+
+    /// Cached only when pinned by number; a hash pin goes to the backend,
+    /// which may resolve it to a different block than the cache key.
+    fn simulate_pinned(
+        &self,
+        bundle: &BundleSpec,
+        state: &PinnedState,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>> {
+        match state.hash {
+            None => self.simulate(bundle, state.block),
+            Some(_) => self.inner.simulate_pinned(bundle, state),
+        }
+    }
+
+    fn trace_pinned(
+        &self,
+        bundle: &BundleSpec,
+        state: &PinnedState,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<u8>, Error>> + Send>> {
+        self.inner.trace_pinned(bundle, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct CountingSimulator {
+        calls: AtomicU64,
+    }
+
+    impl BundleSimulator for CountingSimulator {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                Ok(SimResult {
+                    gas_used: target_block * 1000 + n,
+                    success: true,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    fn bundle() -> BundleSpec {
+        BundleSpec {
+            txs: vec![vec![0x01, 0x02]],
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_simulation_is_served_from_cache() {
+        let inner = Arc::new(CountingSimulator {
+            calls: AtomicU64::new(0),
+        });
+        let (_handle, guard) = RevocationGuard::new();
+        let cache = CachingSimulator::new(inner.clone(), 8, &guard);
+
+        let a = cache.simulate(&bundle(), 105).await.unwrap();
+        let b = cache.simulate(&bundle(), 105).await.unwrap();
+        assert_eq!(a.gas_used, b.gas_used);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+
+        cache.simulate(&bundle(), 106).await.unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn capacity_evicts_oldest() {
+        let inner = Arc::new(CountingSimulator {
+            calls: AtomicU64::new(0),
+        });
+        let (_handle, guard) = RevocationGuard::new();
+        let cache = CachingSimulator::new(inner.clone(), 2, &guard);
+        for block in [101, 102, 103] {
+            cache.simulate(&bundle(), block).await.unwrap();
+        }
+        assert_eq!(cache.len(), 2);
+        cache.simulate(&bundle(), 101).await.unwrap(); // evicted: miss
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn revoking_the_grant_clears_its_cache() {
+        let inner = Arc::new(CountingSimulator {
+            calls: AtomicU64::new(0),
+        });
+        let (handle, guard) = RevocationGuard::new();
+        let cache = CachingSimulator::new(inner, 8, &guard);
+        cache.simulate(&bundle(), 105).await.unwrap();
+        assert_eq!(cache.len(), 1);
+
+        handle.revoke();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+//! Searcher-side control over a grant's bundle contents.
+//!
+//! A [`BundleHandle`] is shared between the searcher and every BundleAccess
+//! server minted under the grant. Servers snapshot the bundle at the start
+//! of each call, so changes are visible to subsequent simulations; a call
+//! already in progress keeps the snapshot it started with. Each change is
+//! atomic: a snapshot sees the bundle wholly before or wholly after an
+//! [`update`](BundleHandle::update), never a mix. The handle is never
+//! exposed to the builder.
+//!
+//! The grant's [`TxPolicy`] lives on the handle, so it applies to every
+//! change as well as to the bundle at graft time.
+
+use crate::access::{check_replay_protection, check_tx_type, BundleSpec};
+use crate::error::MembraneError;
+use capnp::Error;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Restrictions on the txs a grant's bundle may hold.
+#[derive(Clone, Debug, Default)]
+pub struct TxPolicy {
+    /// When set, txs of any other envelope type are rejected with
+    /// `disallowedTxType`.
+    pub allowed_tx_types: Option<HashSet<u8>>,
+    /// Reject pre-EIP-155 legacy txs, which are replayable on any chain
+    /// (`noReplayProtection`). Off by default.
+    pub reject_non_replay_protected: bool,
+}
+
+impl TxPolicy {
+    /// Check `tx`, at position `index` in its bundle.
+    fn check_tx(&self, index: usize, tx: &[u8]) -> Result<(), Error> {
+        if let Some(allowed) = &self.allowed_tx_types {
+            check_tx_type(index, tx, allowed)?;
+        }
+        if self.reject_non_replay_protected {
+            check_replay_protection(index, tx)?;
+        }
+        Ok(())
+    }
+
+    /// Check every tx in `bundle`.
+    pub fn check(&self, bundle: &BundleSpec) -> Result<(), Error> {
+        for (index, tx) in bundle.txs.iter().enumerate() {
+            self.check_tx(index, tx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared, mutable bundle contents.
+#[derive(Clone, Debug)]
+pub struct BundleHandle {
+    bundle: Arc<Mutex<BundleSpec>>,
+    max_txs: Option<usize>,
+    policy: TxPolicy,
+}
+
+impl BundleHandle {
+    /// Wrap `bundle`, optionally capping how many txs it may grow to.
+    pub fn new(bundle: BundleSpec, max_txs: Option<usize>) -> Self {
+        Self {
+            bundle: Arc::new(Mutex::new(bundle)),
+            max_txs,
+            policy: TxPolicy::default(),
+        }
+    }
+
+    /// Enforce `policy` on every later [`update`](Self::update) and
+    /// [`append_tx`](Self::append_tx). Set it before cloning the handle:
+    /// clones taken earlier keep the old policy.
+    pub fn with_policy(mut self, policy: TxPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Check the current contents against the policy, for bundles not
+    /// yet checked (the one passed to [`new`](Self::new)).
+    pub fn check_policy(&self) -> Result<(), Error> {
+        self.policy.check(&self.bundle.lock().unwrap())
+    }
+
+    /// Current contents.
+    pub fn snapshot(&self) -> BundleSpec {
+        self.bundle.lock().unwrap().clone()
+    }
+
+    /// Replace the whole bundle, e.g. to reorder txs or add a backrun.
+    pub fn update(&self, bundle: BundleSpec) -> Result<(), Error> {
+        if bundle.txs.iter().any(Vec::is_empty) {
+            return Err(MembraneError::InvalidTx {
+                detail: "empty transaction".to_string(),
+            }
+            .into());
+        }
+        if let Some(max) = self.max_txs {
+            if bundle.txs.len() > max {
+                return Err(Error::failed(format!(
+                    "maxTxsExceeded: bundle may hold at most {} txs",
+                    max
+                )));
+            }
+        }
+        self.policy.check(&bundle)?;
+        *self.bundle.lock().unwrap() = bundle;
+        Ok(())
+    }
+
+    /// Append a signed transaction to the end of the bundle.
+    pub fn append_tx(&self, tx: Vec<u8>) -> Result<(), Error> {
+        if tx.is_empty() {
+            return Err(MembraneError::InvalidTx {
+                detail: "empty transaction".to_string(),
+            }
+            .into());
+        }
+        let mut bundle = self.bundle.lock().unwrap();
+        if let Some(max) = self.max_txs {
+            if bundle.txs.len() >= max {
+                return Err(Error::failed(format!(
+                    "maxTxsExceeded: bundle already holds {} txs",
+                    max
+                )));
+            }
+        }
+        self.policy.check_tx(bundle.txs.len(), &tx)?;
+        bundle.txs.push(tx);
+        Ok(())
+    }
+}
+
+impl From<BundleSpec> for BundleHandle {
+    fn from(bundle: BundleSpec) -> Self {
+        Self::new(bundle, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_is_visible_in_snapshot() {
+        let handle = BundleHandle::new(
+            BundleSpec {
+                txs: vec![vec![0x01]],
+            },
+            None,
+        );
+        let server_view = handle.clone();
+        handle.append_tx(vec![0x02]).unwrap();
+        assert_eq!(server_view.snapshot().txs, vec![vec![0x01], vec![0x02]]);
+    }
+
+    #[test]
+    fn append_beyond_max_txs_is_rejected() {
+        let handle = BundleHandle::new(BundleSpec { txs: vec![] }, Some(2));
+        handle.append_tx(vec![0x01]).unwrap();
+        handle.append_tx(vec![0x02]).unwrap();
+        let err = handle.append_tx(vec![0x03]).unwrap_err();
+        assert!(err.to_string().contains("maxTxsExceeded"));
+        assert_eq!(handle.snapshot().txs.len(), 2);
+    }
+
+    #[test]
+    fn update_replaces_contents() {
+        let handle = BundleHandle::new(
+            BundleSpec {
+                txs: vec![vec![0x01], vec![0x02]],
+            },
+            Some(2),
+        );
+        let server_view = handle.clone();
+        handle
+            .update(BundleSpec {
+                txs: vec![vec![0x02], vec![0x01]],
+            })
+            .unwrap();
+        assert_eq!(server_view.snapshot().txs, vec![vec![0x02], vec![0x01]]);
+
+        let err = handle
+            .update(BundleSpec {
+                txs: vec![vec![0x01]; 3],
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("maxTxsExceeded"));
+        assert!(handle.update(BundleSpec { txs: vec![vec![]] }).is_err());
+        assert_eq!(handle.snapshot().txs.len(), 2);
+    }
+
+    fn type_policy(allowed: &[u8]) -> TxPolicy {
+        TxPolicy {
+            allowed_tx_types: Some(allowed.iter().copied().collect()),
+            ..TxPolicy::default()
+        }
+    }
+
+    #[test]
+    fn append_of_disallowed_tx_type_is_rejected() {
+        let handle = BundleHandle::new(
+            BundleSpec {
+                txs: vec![vec![0x02, 0xf8]],
+            },
+            None,
+        )
+        .with_policy(type_policy(&[2]));
+        let err = handle.append_tx(vec![0x03, 0xf8]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("disallowedTxType: tx 1 has type 3"));
+        handle.append_tx(vec![0x02, 0xf9]).unwrap();
+        assert_eq!(handle.snapshot().txs.len(), 2);
+    }
+
+    #[test]
+    fn update_with_disallowed_tx_type_is_rejected() {
+        let handle = BundleHandle::new(
+            BundleSpec {
+                txs: vec![vec![0x02, 0xf8]],
+            },
+            None,
+        )
+        .with_policy(type_policy(&[0, 2]));
+        let err = handle
+            .update(BundleSpec {
+                txs: vec![vec![0x02, 0xf8], vec![0x03, 0xf8]],
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("disallowedTxType"));
+        assert_eq!(handle.snapshot().txs, vec![vec![0x02, 0xf8]]);
+    }
+
+    #[test]
+    fn append_of_pre_eip155_legacy_tx_is_rejected() {
+        use crate::rlp::tests::legacy_tx;
+
+        let handle = BundleHandle::new(
+            BundleSpec {
+                txs: vec![legacy_tx(37)],
+            },
+            None,
+        )
+        .with_policy(TxPolicy {
+            reject_non_replay_protected: true,
+            ..TxPolicy::default()
+        });
+        let err = handle.append_tx(legacy_tx(27)).unwrap_err();
+        assert!(err.to_string().contains("noReplayProtection: tx 1"));
+        let err = handle
+            .update(BundleSpec {
+                txs: vec![legacy_tx(28)],
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("noReplayProtection: tx 0"));
+
+        handle.append_tx(legacy_tx(38)).unwrap();
+        assert_eq!(handle.snapshot().txs, vec![legacy_tx(37), legacy_tx(38)]);
+    }
+
+    #[test]
+    fn empty_tx_is_rejected() {
+        let handle = BundleHandle::from(BundleSpec { txs: vec![] });
+        let err = handle.append_tx(vec![]).unwrap_err();
+        assert!(err.to_string().contains("invalidTx"));
+    }
+}
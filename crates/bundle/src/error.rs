@@ -0,0 +1,610 @@
+//! Wire format of membrane errors, for clients.
+//!
+//! Every error this crate (and membrane-core) raises carries its detail in
+//! the capnp `Error`'s text as
+//!
+//! ```text
+//! <code>: <message>
+//! ```
+//!
+//! where `code` is a lowerCamelCase ASCII identifier naming the failure
+//! (`staleEpoch`, `revoked`, `blockOutOfWindow`, ...) and `message` is
+//! human-readable detail that may change between releases. The code is the
+//! stable part: clients branch on it, not on the message. The capnp error
+//! kind (`failed`, `overloaded`, `unimplemented`) is carried separately.
+//!
+//! Errors that crossed an RPC boundary gain a `remote exception: ` prefix
+//! from capnp-rpc; [`decode`] strips it.
+//!
+//! Guard failures are raised as a [`MembraneError`], whose fields follow
+//! the message in braces:
+//!
+//! ```text
+//! blockOutOfWindow: target 99 not in [100, 110] {target=99,windows=100-110}
+//! ```
+//!
+//! Fields are `key=value`, comma-separated, with integers in decimal and
+//! window lists as `from-until` pairs joined by `;`. `revoked`,
+//! `builderAuthFailed`, `invalidTx` and `invalidSnapshot` carry no fields:
+//! their whole message is free-form detail, such as the searcher's reason.
+//! [`MembraneError::from_error`] recovers the variant and its fields.
+
+use capnp::{Error, ErrorKind};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Prefix capnp-rpc adds to errors received from a peer.
+const REMOTE_PREFIX: &str = "remote exception: ";
+
+/// A decoded membrane error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorDetail<'a> {
+    pub code: &'a str,
+    pub message: &'a str,
+}
+
+/// Build the error text for `code` and `message`.
+pub fn encode(code: &str, message: &str) -> String {
+    format!("{}: {}", code, message)
+}
+
+/// Split an error's text into code and message. `None` if the text does
+/// not follow the membrane format, e.g. transport errors.
+pub fn decode(err: &Error) -> Option<ErrorDetail<'_>> {
+    let text = err.extra.as_str();
+    let text = text.strip_prefix(REMOTE_PREFIX).unwrap_or(text);
+    let (code, message) = text.split_once(": ")?;
+    let mut chars = code.chars();
+    if !chars.next()?.is_ascii_lowercase() || !chars.all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(ErrorDetail { code, message })
+}
+
+/// The error's code, if it has one.
+pub fn code(err: &Error) -> Option<&str> {
+    decode(err).map(|d| d.code)
+}
+
+/// A guard failure, with the fields a client may act on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MembraneError {
+    /// The grant was revoked, for `reason`.
+    Revoked { reason: String },
+    /// The session's epoch is no longer current.
+    StaleEpoch,
+    /// `target` lies outside every window of the grant.
+    BlockOutOfWindow {
+        target: u64,
+        windows: Vec<(u64, u64)>,
+    },
+    /// The grant's wall-clock lifetime has elapsed.
+    GrantExpired,
+    /// All `max_calls` calls of the grant's budget are used.
+    CallBudgetExhausted { max_calls: u64 },
+    /// Over the grant's rate limit.
+    RateLimited { refill_per_sec: f64, burst: u32 },
+    /// `include` needs a successful `simulate` for `block` first.
+    SimulateRequiredFirst { block: u64 },
+    /// The bundle is already included at `block`.
+    AlreadyIncluded { block: u64 },
+    /// The epoch adopted at `adopted_block` is `age` blocks old, short of
+    /// `min_age`.
+    EpochTooFresh {
+        adopted_block: u64,
+        age: u64,
+        min_age: u64,
+    },
+    /// Tx `index` has a type the grant does not allow.
+    DisallowedTxType { index: u64, tx_type: u8 },
+    /// Tx `index` is a legacy tx without EIP-155 replay protection.
+    NoReplayProtection { index: u64 },
+    /// `feature` needs a newer schema version than was negotiated.
+    FeatureUnavailable { feature: String },
+    /// `limit` simulations are already in progress.
+    TooManyConcurrentCalls { limit: u64 },
+    /// The backend took longer than `timeout_ms`.
+    SimTimeout { timeout_ms: u64 },
+    /// `requested` blocks asked for, more than `max`.
+    RangeTooLarge { requested: u64, max: u64 },
+    /// No recent result matches the given hash.
+    UnknownResult,
+    /// `simulateRange` was asked for `from` after `to`.
+    InvalidRange { from: u64, to: u64 },
+    /// The grant's window `[valid_from, valid_until]` ends before `head`.
+    WindowInPast {
+        valid_from: u64,
+        valid_until: u64,
+        head: u64,
+    },
+    /// The grafting peer did not prove it holds the builder key.
+    BuilderAuthFailed { detail: String },
+    /// Over the membrane's grant issuance rate.
+    IssuanceRateLimited,
+    /// The grant's quantization forbids traces.
+    TraceUnavailable,
+    /// The revocation handle was superseded by `rotate`.
+    StaleRevocationHandle,
+    /// A registry snapshot could not be loaded.
+    InvalidSnapshot { detail: String },
+    /// A transaction could not be parsed.
+    InvalidTx { detail: String },
+}
+
+impl MembraneError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Revoked { .. } => "revoked",
+            Self::StaleEpoch => "staleEpoch",
+            Self::BlockOutOfWindow { .. } => "blockOutOfWindow",
+            Self::GrantExpired => "grantExpired",
+            Self::CallBudgetExhausted { .. } => "callBudgetExhausted",
+            Self::RateLimited { .. } => "rateLimited",
+            Self::SimulateRequiredFirst { .. } => "simulateRequiredFirst",
+            Self::AlreadyIncluded { .. } => "alreadyIncluded",
+            Self::EpochTooFresh { .. } => "epochTooFresh",
+            Self::DisallowedTxType { .. } => "disallowedTxType",
+            Self::NoReplayProtection { .. } => "noReplayProtection",
+            Self::FeatureUnavailable { .. } => "featureUnavailable",
+            Self::TooManyConcurrentCalls { .. } => "tooManyConcurrentCalls",
+            Self::SimTimeout { .. } => "simTimeout",
+            Self::RangeTooLarge { .. } => "rangeTooLarge",
+            Self::UnknownResult => "unknownResult",
+            Self::InvalidRange { .. } => "invalidRange",
+            Self::WindowInPast { .. } => "windowInPast",
+            Self::BuilderAuthFailed { .. } => "builderAuthFailed",
+            Self::IssuanceRateLimited => "issuanceRateLimited",
+            Self::TraceUnavailable => "traceUnavailable",
+            Self::StaleRevocationHandle => "staleRevocationHandle",
+            Self::InvalidSnapshot { .. } => "invalidSnapshot",
+            Self::InvalidTx { .. } => "invalidTx",
+        }
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::RateLimited { .. }
+            | Self::TooManyConcurrentCalls { .. }
+            | Self::IssuanceRateLimited => ErrorKind::Overloaded,
+            Self::FeatureUnavailable { .. } | Self::TraceUnavailable => ErrorKind::Unimplemented,
+            _ => ErrorKind::Failed,
+        }
+    }
+
+    /// Human-readable detail, without the field trailer.
+    fn message(&self) -> String {
+        match self {
+            Self::Revoked { reason } => reason.clone(),
+            Self::StaleEpoch => "session epoch no longer current".to_string(),
+            Self::BlockOutOfWindow { target, windows } => {
+                let windows: Vec<String> = windows
+                    .iter()
+                    .map(|(from, until)| format!("[{}, {}]", from, until))
+                    .collect();
+                format!("target {} not in {}", target, windows.join(", "))
+            }
+            Self::GrantExpired => "grant validity period has elapsed".to_string(),
+            Self::CallBudgetExhausted { max_calls } => format!("all {} calls used", max_calls),
+            Self::RateLimited {
+                refill_per_sec,
+                burst,
+            } => format!("over {} calls/s (burst {})", refill_per_sec, burst),
+            Self::SimulateRequiredFirst { block } => {
+                format!("no successful simulate for block {}", block)
+            }
+            Self::AlreadyIncluded { block } => format!("at block {}", block),
+            Self::EpochTooFresh {
+                adopted_block,
+                age,
+                min_age,
+            } => format!(
+                "epoch adopted at block {} is {} blocks old, need {}",
+                adopted_block, age, min_age
+            ),
+            Self::DisallowedTxType { index, tx_type } => {
+                format!("tx {} has type {}", index, tx_type)
+            }
+            Self::NoReplayProtection { index } => {
+                format!("tx {} is a pre-EIP-155 legacy tx", index)
+            }
+            Self::FeatureUnavailable { feature } => {
+                format!("{} requires a newer schema version", feature)
+            }
+            Self::TooManyConcurrentCalls { limit } => {
+                format!("{} simulations already in progress", limit)
+            }
+            Self::SimTimeout { timeout_ms } => {
+                format!("backend took longer than {}ms", timeout_ms)
+            }
+            Self::RangeTooLarge { requested, max } => {
+                format!("{} blocks requested, at most {}", requested, max)
+            }
+            Self::UnknownResult => "no recent result with that hash".to_string(),
+            Self::InvalidRange { from, to } => {
+                format!("fromBlock {} is after toBlock {}", from, to)
+            }
+            Self::WindowInPast {
+                valid_from,
+                valid_until,
+                head,
+            } => format!(
+                "window [{}, {}] ends before head block {}",
+                valid_from, valid_until, head
+            ),
+            Self::BuilderAuthFailed { detail }
+            | Self::InvalidSnapshot { detail }
+            | Self::InvalidTx { detail } => detail.clone(),
+            Self::IssuanceRateLimited => "too many grants minted".to_string(),
+            Self::TraceUnavailable => "grant restricts result disclosure".to_string(),
+            Self::StaleRevocationHandle => "handle has been rotated".to_string(),
+        }
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        fn f(key: &'static str, value: impl Display) -> (&'static str, String) {
+            (key, value.to_string())
+        }
+        match self {
+            Self::Revoked { .. }
+            | Self::StaleEpoch
+            | Self::GrantExpired
+            | Self::UnknownResult
+            | Self::BuilderAuthFailed { .. }
+            | Self::IssuanceRateLimited
+            | Self::TraceUnavailable
+            | Self::StaleRevocationHandle
+            | Self::InvalidSnapshot { .. }
+            | Self::InvalidTx { .. } => vec![],
+            Self::BlockOutOfWindow { target, windows } => {
+                let windows: Vec<String> = windows
+                    .iter()
+                    .map(|(from, until)| format!("{}-{}", from, until))
+                    .collect();
+                vec![f("target", target), f("windows", windows.join(";"))]
+            }
+            Self::CallBudgetExhausted { max_calls } => vec![f("maxCalls", max_calls)],
+            Self::RateLimited {
+                refill_per_sec,
+                burst,
+            } => vec![f("refillPerSec", refill_per_sec), f("burst", burst)],
+            Self::SimulateRequiredFirst { block } | Self::AlreadyIncluded { block } => {
+                vec![f("block", block)]
+            }
+            Self::EpochTooFresh {
+                adopted_block,
+                age,
+                min_age,
+            } => vec![
+                f("adoptedBlock", adopted_block),
+                f("age", age),
+                f("minAge", min_age),
+            ],
+            Self::DisallowedTxType { index, tx_type } => {
+                vec![f("index", index), f("txType", tx_type)]
+            }
+            Self::NoReplayProtection { index } => vec![f("index", index)],
+            Self::FeatureUnavailable { feature } => vec![f("feature", feature)],
+            Self::TooManyConcurrentCalls { limit } => vec![f("limit", limit)],
+            Self::SimTimeout { timeout_ms } => vec![f("timeoutMs", timeout_ms)],
+            Self::RangeTooLarge { requested, max } => {
+                vec![f("requested", requested), f("max", max)]
+            }
+            Self::InvalidRange { from, to } => vec![f("from", from), f("to", to)],
+            Self::WindowInPast {
+                valid_from,
+                valid_until,
+                head,
+            } => vec![
+                f("validFrom", valid_from),
+                f("validUntil", valid_until),
+                f("head", head),
+            ],
+        }
+    }
+
+    /// Recover the variant from an error raised by [`into`](Into::into),
+    /// locally or across RPC. `None` for other errors, or if a field is
+    /// missing or malformed.
+    pub fn from_error(err: &Error) -> Option<Self> {
+        let detail = decode(err)?;
+        let text = detail.message.to_string();
+        match detail.code {
+            "revoked" => return Some(Self::Revoked { reason: text }),
+            "builderAuthFailed" => return Some(Self::BuilderAuthFailed { detail: text }),
+            "invalidSnapshot" => return Some(Self::InvalidSnapshot { detail: text }),
+            "invalidTx" => return Some(Self::InvalidTx { detail: text }),
+            _ => {}
+        }
+        let fields: HashMap<&str, &str> = match detail.message.rsplit_once(" {") {
+            Some((_, trailer)) => trailer
+                .strip_suffix('}')?
+                .split(',')
+                .filter(|kv| !kv.is_empty())
+                .map(|kv| kv.split_once('='))
+                .collect::<Option<_>>()?,
+            None => HashMap::new(),
+        };
+        let num = |key: &str| fields.get(key)?.parse::<u64>().ok();
+        Some(match detail.code {
+            "staleEpoch" => Self::StaleEpoch,
+            "blockOutOfWindow" => Self::BlockOutOfWindow {
+                target: num("target")?,
+                windows: fields
+                    .get("windows")?
+                    .split(';')
+                    .map(|w| {
+                        let (from, until) = w.split_once('-')?;
+                        Some((from.parse().ok()?, until.parse().ok()?))
+                    })
+                    .collect::<Option<_>>()?,
+            },
+            "grantExpired" => Self::GrantExpired,
+            "callBudgetExhausted" => Self::CallBudgetExhausted {
+                max_calls: num("maxCalls")?,
+            },
+            "rateLimited" => Self::RateLimited {
+                refill_per_sec: fields.get("refillPerSec")?.parse().ok()?,
+                burst: fields.get("burst")?.parse().ok()?,
+            },
+            "simulateRequiredFirst" => Self::SimulateRequiredFirst {
+                block: num("block")?,
+            },
+            "alreadyIncluded" => Self::AlreadyIncluded {
+                block: num("block")?,
+            },
+            "epochTooFresh" => Self::EpochTooFresh {
+                adopted_block: num("adoptedBlock")?,
+                age: num("age")?,
+                min_age: num("minAge")?,
+            },
+            "disallowedTxType" => Self::DisallowedTxType {
+                index: num("index")?,
+                tx_type: fields.get("txType")?.parse().ok()?,
+            },
+            "noReplayProtection" => Self::NoReplayProtection {
+                index: num("index")?,
+            },
+            "featureUnavailable" => Self::FeatureUnavailable {
+                feature: fields.get("feature")?.to_string(),
+            },
+            "tooManyConcurrentCalls" => Self::TooManyConcurrentCalls {
+                limit: num("limit")?,
+            },
+            "simTimeout" => Self::SimTimeout {
+                timeout_ms: num("timeoutMs")?,
+            },
+            "rangeTooLarge" => Self::RangeTooLarge {
+                requested: num("requested")?,
+                max: num("max")?,
+            },
+            "unknownResult" => Self::UnknownResult,
+            "invalidRange" => Self::InvalidRange {
+                from: num("from")?,
+                to: num("to")?,
+            },
+            "windowInPast" => Self::WindowInPast {
+                valid_from: num("validFrom")?,
+                valid_until: num("validUntil")?,
+                head: num("head")?,
+            },
+            "issuanceRateLimited" => Self::IssuanceRateLimited,
+            "traceUnavailable" => Self::TraceUnavailable,
+            "staleRevocationHandle" => Self::StaleRevocationHandle,
+            _ => return None,
+        })
+    }
+}
+
+impl From<MembraneError> for Error {
+    fn from(e: MembraneError) -> Self {
+        let fields = e.fields();
+        let mut text = encode(e.code(), &e.message());
+        if !fields.is_empty() {
+            let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            text = format!("{} {{{}}}", text, fields.join(","));
+        }
+        match e.kind() {
+            ErrorKind::Overloaded => Error::overloaded(text),
+            ErrorKind::Unimplemented => Error::unimplemented(text),
+            _ => Error::failed(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{BlockWindowGuard, TimeWindowGuard};
+    use crate::bundle_capnp;
+    use crate::revocation::RevocationGuard;
+
+    #[test]
+    fn encoded_errors_decode_to_their_parts() {
+        for (code, message) in [
+            ("revoked", "bundle landed elsewhere"),
+            ("blockOutOfWindow", "target 99 not in [100, 110]"),
+            ("rateLimited", "no tokens: retry later"),
+        ] {
+            for err in [
+                Error::failed(encode(code, message)),
+                Error::overloaded(encode(code, message)),
+            ] {
+                let detail = decode(&err).unwrap();
+                assert_eq!(detail, ErrorDetail { code, message });
+            }
+        }
+    }
+
+    #[test]
+    fn guard_errors_follow_the_format() {
+        let (handle, guard) = RevocationGuard::new();
+        handle.revoke_with_reason("done".to_string());
+        let err = guard.check().unwrap_err();
+        assert_eq!(
+            decode(&err),
+            Some(ErrorDetail {
+                code: "revoked",
+                message: "done"
+            })
+        );
+
+        let err = BlockWindowGuard::single(100, 110).check(99).unwrap_err();
+        assert_eq!(code(&err), Some("blockOutOfWindow"));
+
+        let expired = TimeWindowGuard {
+            valid_until: std::time::Instant::now() - std::time::Duration::from_secs(1),
+        };
+        assert_eq!(code(&expired.check().unwrap_err()), Some("grantExpired"));
+    }
+
+    #[test]
+    fn unstructured_text_has_no_code() {
+        assert_eq!(decode(&Error::failed("connection reset".to_string())), None);
+        assert_eq!(decode(&Error::failed("Not A Code: x".to_string())), None);
+    }
+
+    struct Failing;
+
+    #[allow(refining_impl_trait)]
+    impl bundle_capnp::health::Server for Failing {
+        fn ping(
+            self: capnp::capability::Rc<Self>,
+            _params: bundle_capnp::health::PingParams,
+            _results: bundle_capnp::health::PingResults,
+        ) -> capnp::capability::Promise<(), Error> {
+            capnp::capability::Promise::err(Error::overloaded(encode(
+                "tooManyConcurrentCalls",
+                "4 simulations already in progress",
+            )))
+        }
+    }
+
+    fn every_variant() -> Vec<MembraneError> {
+        vec![
+            MembraneError::Revoked {
+                reason: "bundle landed {elsewhere}".to_string(),
+            },
+            MembraneError::StaleEpoch,
+            MembraneError::BlockOutOfWindow {
+                target: 99,
+                windows: vec![(100, 101), (108, 110)],
+            },
+            MembraneError::GrantExpired,
+            MembraneError::CallBudgetExhausted { max_calls: 3 },
+            MembraneError::RateLimited {
+                refill_per_sec: 2.5,
+                burst: 4,
+            },
+            MembraneError::SimulateRequiredFirst { block: 105 },
+            MembraneError::AlreadyIncluded { block: 106 },
+            MembraneError::EpochTooFresh {
+                adopted_block: 100,
+                age: 1,
+                min_age: 3,
+            },
+            MembraneError::DisallowedTxType {
+                index: 1,
+                tx_type: 3,
+            },
+            MembraneError::NoReplayProtection { index: 0 },
+            MembraneError::FeatureUnavailable {
+                feature: "simulateRange".to_string(),
+            },
+            MembraneError::TooManyConcurrentCalls { limit: 4 },
+            MembraneError::SimTimeout { timeout_ms: 250 },
+            MembraneError::RangeTooLarge {
+                requested: 17,
+                max: 16,
+            },
+            MembraneError::UnknownResult,
+            MembraneError::InvalidRange { from: 110, to: 105 },
+            MembraneError::WindowInPast {
+                valid_from: 100,
+                valid_until: 110,
+                head: 111,
+            },
+            MembraneError::BuilderAuthFailed {
+                detail: "signature does not recover to builder_pubkey".to_string(),
+            },
+            MembraneError::IssuanceRateLimited,
+            MembraneError::TraceUnavailable,
+            MembraneError::StaleRevocationHandle,
+            MembraneError::InvalidSnapshot {
+                detail: "truncated".to_string(),
+            },
+            MembraneError::InvalidTx {
+                detail: "unknown envelope byte 0x80 {x}".to_string(),
+            },
+        ]
+    }
+
+    struct Raising(MembraneError);
+
+    #[allow(refining_impl_trait)]
+    impl bundle_capnp::health::Server for Raising {
+        fn ping(
+            self: capnp::capability::Rc<Self>,
+            _params: bundle_capnp::health::PingParams,
+            _results: bundle_capnp::health::PingResults,
+        ) -> capnp::capability::Promise<(), Error> {
+            capnp::capability::Promise::err(self.0.clone().into())
+        }
+    }
+
+    #[tokio::test]
+    async fn every_variant_round_trips_through_rpc() {
+        for variant in every_variant() {
+            let local: Error = variant.clone().into();
+            assert_eq!(code(&local), Some(variant.code()));
+            assert_eq!(MembraneError::from_error(&local), Some(variant.clone()));
+
+            let client: bundle_capnp::health::Client =
+                capnp_rpc::new_client(Raising(variant.clone()));
+            let err = client.ping_request().send().promise.await.err().unwrap();
+            assert_eq!(
+                std::mem::discriminant(&err.kind),
+                std::mem::discriminant(&local.kind)
+            );
+            assert_eq!(MembraneError::from_error(&err), Some(variant));
+        }
+    }
+
+    #[test]
+    fn typed_errors_keep_their_human_message() {
+        let err: Error = MembraneError::BlockOutOfWindow {
+            target: 99,
+            windows: vec![(100, 110)],
+        }
+        .into();
+        assert_eq!(
+            err.extra,
+            "blockOutOfWindow: target 99 not in [100, 110] {target=99,windows=100-110}"
+        );
+        let err = BlockWindowGuard::single(100, 110).check(99).unwrap_err();
+        assert_eq!(
+            MembraneError::from_error(&err),
+            Some(MembraneError::BlockOutOfWindow {
+                target: 99,
+                windows: vec![(100, 110)],
+            })
+        );
+    }
+
+    #[test]
+    fn missing_fields_do_not_decode() {
+        let err = Error::failed("callBudgetExhausted: all 3 calls used".to_string());
+        assert_eq!(MembraneError::from_error(&err), None);
+        let err = Error::failed("alreadyIncluded: at block 5 {block=x}".to_string());
+        assert_eq!(MembraneError::from_error(&err), None);
+    }
+
+    #[tokio::test]
+    async fn detail_survives_rpc() {
+        let client: bundle_capnp::health::Client = capnp_rpc::new_client(Failing);
+        let err = client.ping_request().send().promise.await.err().unwrap();
+        assert!(matches!(err.kind, capnp::ErrorKind::Overloaded));
+        let detail = decode(&err).unwrap();
+        assert_eq!(detail.code, "tooManyConcurrentCalls");
+        assert_eq!(detail.message, "4 simulations already in progress");
+    }
+}
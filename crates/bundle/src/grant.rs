@@ -1,26 +1,313 @@
 //! BundleGrantBuilder: mints BundleAccess capabilities during graft().
 
-use crate::access::{BlockWindowGuard, BundleAccessServer, BundleSimulator, BundleSpec};
+use crate::access::{
+    BlockWindowGuard, BundleAccessServer, BundleSimulator, BundleSpec, CallBudgetGuard,
+    EpochAgeGuard, GuardObserver, InclusionGuard, PinnedState, RateLimitGuard, RecentResults,
+    ResultQuantization, SimulateFirstGuard, TimeWindowGuard, SCHEMA_VERSION,
+};
+use crate::audit::{record_audit, AuditEvent, AuditSampler, AuditSink};
+use crate::auth::{grant_message, recover_signer, signer_message, Authenticator, ChallengeNonces};
 use crate::bundle_capnp;
+use crate::cache::CachingSimulator;
+use crate::contents::BundleHandle;
+use crate::error::MembraneError;
+use crate::latency::LatencyTracker;
+use crate::pubkey::BuilderKey;
+use crate::registry::InFlightTracker;
 use crate::revocation::{RevocationGuard, RevocationHandle};
+use capnp::capability::Promise;
 use capnp::Error;
-use capnp_rpc::new_client;
+use capnp_rpc::{new_client, pry};
 use membrane_core::epoch::Epoch;
-use membrane_core::{EpochGuard, MembraneServer, SessionExtensionBuilder};
-use std::sync::Arc;
+use membrane_core::stem_capnp;
+use membrane_core::{
+    AdoptedBlockMap, EpochChainMap, EpochGuard, MembraneServer, SessionExtensionBuilder,
+};
+use std::cell::Cell;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::sync::watch;
 
+/// What to do when a grant's whole window is already behind the chain head
+/// at graft time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PastWindowPolicy {
+    /// Log a warning and issue the grant anyway (tolerates clock skew).
+    #[default]
+    Warn,
+    /// Fail the graft with `windowInPast`.
+    Reject,
+}
+
 /// Builds the BundleGrant session extension during `graft()`.
 ///
 /// Implements `SessionExtensionBuilder<bundle_grant::Owned>` — the callback
 /// that `MembraneServer` calls to fill the session extension field.
+///
+/// Create one with [`new()`](Self::new), which leaves every optional guard
+/// off, then set the fields to opt in.
+#[non_exhaustive]
 pub struct BundleGrantBuilder {
-    pub bundle: BundleSpec,
+    /// Shared with the searcher, who may keep appending to it. Its
+    /// [`TxPolicy`](crate::contents::TxPolicy) is checked at graft and on
+    /// every change.
+    pub bundle: BundleHandle,
     pub valid_from: u64,
     pub valid_until: u64,
+    /// Optional disjoint inclusive ranges, all within `[valid_from,
+    /// valid_until]`; when set, only blocks inside one of them are usable.
+    pub windows: Option<Vec<(u64, u64)>>,
+    /// Optional wall-clock lifetime, counted from the first graft and shared
+    /// by every session; calls after it fail with `grantExpired`.
+    pub valid_for: Option<Duration>,
+    /// Opt-in: simulate every call under every session against the head
+    /// block at the first graft, per `chain_map` and by hash where it
+    /// knows one, so results don't drift as the chain advances.
+    pub pin_state_at_issuance: bool,
+    /// Compressed, uncompressed or address encoding; see [`BuilderKey`].
     pub builder_pubkey: Vec<u8>,
     pub simulator: Arc<dyn BundleSimulator>,
     pub revocation_guard: RevocationGuard,
+    /// Optional sink recording issuance, simulate and include events.
+    pub audit: Option<Arc<dyn AuditSink>>,
+    /// Optional: persist a random fraction of full simulation results to
+    /// `audit`, shared across every session minted from this builder.
+    pub audit_sampler: Option<AuditSampler>,
+    /// Rounding applied to results before they reach the builder.
+    pub quantization: ResultQuantization,
+    /// Opt-in: require a successful `simulate` for a block before `include`.
+    pub simulate_first: Option<SimulateFirstGuard>,
+    /// Opt-in gas cap: simulations using more are reported to the builder
+    /// as failed with `gasLimitExceeded`.
+    pub max_gas: Option<u64>,
+    /// Block the bundle has been included at, shared by every session
+    /// minted from this builder.
+    pub inclusion: InclusionGuard,
+    /// Opt-in: let `include` succeed at more than one block, for searchers
+    /// who intend multi-block inclusion.
+    pub allow_multi_inclusion: bool,
+    /// Blocks the current epoch must have been the head, per `chain_map`,
+    /// before `include` commits; `0` disables the check.
+    pub min_epoch_age: u64,
+    /// Opt-in cap on simulate/include calls; shared by every session minted
+    /// from this builder.
+    pub call_budget: Option<CallBudgetGuard>,
+    /// Opt-in token bucket on simulate/include calls; shared by every session
+    /// minted from this builder.
+    pub rate_limit: Option<RateLimitGuard>,
+    /// Opt-in token bucket on grafts. Share one guard across every builder
+    /// in the process to cap its total issuance rate.
+    pub issuance_limit: Option<RateLimitGuard>,
+    /// Told about every guard decision on minted capabilities.
+    pub guard_observer: Option<Arc<dyn GuardObserver>>,
+    /// Simulate latency histogram; share one tracker across grants for a
+    /// global view.
+    pub latency: Option<LatencyTracker>,
+    /// Resolves the chain head used for graft-time window checks and
+    /// `min_epoch_age`.
+    pub chain_map: Arc<dyn EpochChainMap>,
+    /// Whether a window ending before the head warns or fails the graft.
+    pub past_window: PastWindowPolicy,
+    /// When set, the grant gets a result cache of this many entries,
+    /// shared by every session minted from this builder and cleared on
+    /// revocation.
+    pub result_cache: Option<usize>,
+    /// The cache in front of `simulator`, built on the first graft when
+    /// `result_cache` is set.
+    pub cached_simulator: OnceLock<Arc<dyn BundleSimulator>>,
+    /// The wall-clock deadline, fixed on the first graft when `valid_for`
+    /// is set.
+    pub time_window: OnceLock<TimeWindowGuard>,
+    /// The state pinned on the first graft when `pin_state_at_issuance`
+    /// is set.
+    pub pinned_state: OnceLock<PinnedState>,
+    /// Shared with a [`GrantRegistry`](crate::registry::GrantRegistry) so
+    /// shutdown can wait for this grant's simulations.
+    pub in_flight: Option<InFlightTracker>,
+    /// Opt-in: clamp out-of-window simulate targets instead of rejecting.
+    pub clamp_to_window: bool,
+    /// Opt-in: zstd-compress traces of
+    /// [`TRACE_COMPRESSION_THRESHOLD`](crate::access::TRACE_COMPRESSION_THRESHOLD)
+    /// bytes or more.
+    pub compress_traces: bool,
+    /// Cap on simulations in progress at once across every session minted
+    /// from this builder; calls beyond it fail with `tooManyConcurrentCalls`.
+    pub max_concurrent_calls: Option<usize>,
+    /// Simulations in progress, counted against `max_concurrent_calls`;
+    /// shared by every session minted from this builder.
+    pub active_calls: InFlightTracker,
+    /// Per-call deadline on backend simulations; calls past it fail with
+    /// `simTimeout`.
+    pub simulate_timeout: Option<Duration>,
+    /// Require the grafting peer to prove it holds `builder_pubkey` by
+    /// signing `challenge` through the `Signer` passed to `graft`.
+    pub verify_builder_auth: bool,
+    /// Challenge the builder signs; empty means the current epoch's head
+    /// bytes, which change every epoch.
+    pub challenge: Vec<u8>,
+    /// Source of the per-graft nonce signed along with `challenge`.
+    pub challenge_nonces: ChallengeNonces,
+    /// When set, attests each grant (over [`grant_message`]) and each
+    /// `simulate` result hash, so the builder can check both came from
+    /// the searcher.
+    pub attestor: Option<Arc<dyn Authenticator>>,
+}
+
+impl BundleGrantBuilder {
+    /// A grant of `bundle` to `builder_pubkey` for blocks `[valid_from,
+    /// valid_until]`, revoked through `revocation_guard`'s handle.
+    pub fn new(
+        bundle: impl Into<BundleHandle>,
+        valid_from: u64,
+        valid_until: u64,
+        builder_pubkey: Vec<u8>,
+        simulator: Arc<dyn BundleSimulator>,
+        revocation_guard: RevocationGuard,
+    ) -> Self {
+        Self {
+            bundle: bundle.into(),
+            valid_from,
+            valid_until,
+            windows: None,
+            valid_for: None,
+            pin_state_at_issuance: false,
+            builder_pubkey,
+            simulator,
+            revocation_guard,
+            audit: None,
+            audit_sampler: None,
+            quantization: ResultQuantization::default(),
+            simulate_first: None,
+            max_gas: None,
+            inclusion: InclusionGuard::default(),
+            allow_multi_inclusion: false,
+            min_epoch_age: 0,
+            call_budget: None,
+            rate_limit: None,
+            issuance_limit: None,
+            guard_observer: None,
+            latency: None,
+            chain_map: Arc::new(AdoptedBlockMap),
+            past_window: PastWindowPolicy::default(),
+            result_cache: None,
+            cached_simulator: OnceLock::new(),
+            time_window: OnceLock::new(),
+            pinned_state: OnceLock::new(),
+            in_flight: None,
+            clamp_to_window: false,
+            compress_traces: false,
+            max_concurrent_calls: None,
+            active_calls: InFlightTracker::default(),
+            simulate_timeout: None,
+            verify_builder_auth: false,
+            challenge: Vec::new(),
+            challenge_nonces: ChallengeNonces::default(),
+            attestor: None,
+        }
+    }
+
+    /// Run this grant's guard stack against a hypothetical `target_block`
+    /// without minting a capability or touching the simulator.
+    ///
+    /// Checks the builder key, tx types, revocation, block window, call
+    /// budget and wall-clock window, which has not started before the
+    /// first graft. The epoch guard is omitted: a session minted now would
+    /// be issued under the current epoch and so always pass it.
+    pub fn dry_run(&self, target_block: u64) -> Result<(), Error> {
+        BuilderKey::parse(&self.builder_pubkey)?;
+        self.bundle.check_policy()?;
+        self.revocation_guard.check()?;
+        self.block_window().check(target_block)?;
+        if let Some(time_window) = self.time_window.get() {
+            time_window.check()?;
+        }
+        if let Some(budget) = &self.call_budget {
+            budget.check()?;
+        }
+        Ok(())
+    }
+
+    /// The simulator for a newly minted session: the backend, behind the
+    /// grant's cache if one is configured.
+    fn grant_simulator(&self) -> Arc<dyn BundleSimulator> {
+        match self.result_cache {
+            Some(capacity) => self
+                .cached_simulator
+                .get_or_init(|| {
+                    Arc::new(CachingSimulator::new(
+                        self.simulator.clone(),
+                        capacity,
+                        &self.revocation_guard,
+                    ))
+                })
+                .clone(),
+            None => self.simulator.clone(),
+        }
+    }
+
+    /// The grant's wall-clock window, started by the first graft, so later
+    /// grafts can't extend it.
+    fn grant_time_window(&self) -> Option<TimeWindowGuard> {
+        let valid_for = self.valid_for?;
+        Some(
+            *self
+                .time_window
+                .get_or_init(|| TimeWindowGuard::valid_for(valid_for)),
+        )
+    }
+
+    /// The grant's pinned state, captured under `epoch` by the first graft
+    /// so every session simulates against the same block.
+    fn grant_pinned_state(&self, epoch: &Epoch) -> Option<PinnedState> {
+        if !self.pin_state_at_issuance {
+            return None;
+        }
+        let state = self.pinned_state.get_or_init(|| PinnedState {
+            block: self.chain_map.head_block(epoch),
+            hash: self.chain_map.head_hash(epoch),
+        });
+        Some(state.clone())
+    }
+
+    /// `Signer` domain for the builder-auth challenge under `epoch`.
+    fn challenge_domain(&self, epoch: &Epoch) -> String {
+        let challenge = if self.challenge.is_empty() {
+            &epoch.head
+        } else {
+            &self.challenge
+        };
+        let hex: String = challenge.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("membrane-bundle/builder-auth/{}", hex)
+    }
+
+    fn block_window(&self) -> BlockWindowGuard {
+        match &self.windows {
+            Some(ranges) => BlockWindowGuard::multi(ranges.clone()),
+            None => BlockWindowGuard::single(self.valid_from, self.valid_until),
+        }
+    }
+
+    /// Detect a grant that is dead on arrival: its window ends before the
+    /// current head block.
+    fn check_window_not_past(&self, epoch: &Epoch) -> Result<(), Error> {
+        let head = self.chain_map.head_block(epoch);
+        if self.valid_until >= head {
+            return Ok(());
+        }
+        let err: Error = MembraneError::WindowInPast {
+            valid_from: self.valid_from,
+            valid_until: self.valid_until,
+            head,
+        }
+        .into();
+        match self.past_window {
+            PastWindowPolicy::Warn => {
+                tracing::warn!("{}", err.extra);
+                Ok(())
+            }
+            PastWindowPolicy::Reject => Err(err),
+        }
+    }
 }
 
 impl SessionExtensionBuilder<bundle_capnp::bundle_grant::Owned> for BundleGrantBuilder {
@@ -29,30 +316,139 @@ impl SessionExtensionBuilder<bundle_capnp::bundle_grant::Owned> for BundleGrantB
         guard: &EpochGuard,
         mut builder: bundle_capnp::bundle_grant::Builder<'_>,
     ) -> Result<(), Error> {
+        let epoch = guard.receiver.borrow().clone();
+        self.check_window_not_past(&epoch)?;
+        self.bundle.check_policy()?;
+
+        let builder_key = BuilderKey::parse(&self.builder_pubkey)?;
+        // Taken before anything is signed, so a refused graft costs the
+        // attestor nothing.
+        if let Some(limit) = &self.issuance_limit {
+            limit
+                .check()
+                .map_err(|_| Error::from(MembraneError::IssuanceRateLimited))?;
+        }
+
         builder.set_valid_from_block(self.valid_from);
         builder.set_valid_until_block(self.valid_until);
-        builder.set_builder_pubkey(&self.builder_pubkey);
+        builder.set_builder_pubkey(&builder_key.to_bytes());
+        if let Some(attestor) = &self.attestor {
+            let msg = grant_message(&builder_key.to_bytes(), self.valid_from, self.valid_until);
+            builder.set_attestation(&attestor.sign(&msg)?);
+        }
+        record_audit(
+            &self.audit,
+            AuditEvent::GrantIssued {
+                builder_pubkey: builder_key.to_bytes(),
+                valid_from: self.valid_from,
+                valid_until: self.valid_until,
+            },
+        );
 
         let server = BundleAccessServer {
             epoch_guard: guard.clone(),
             revocation_guard: self.revocation_guard.clone(),
-            block_window: BlockWindowGuard {
-                valid_from: self.valid_from,
-                valid_until: self.valid_until,
-            },
+            block_window: self.block_window(),
+            time_window: self.grant_time_window(),
+            pinned_state: self.grant_pinned_state(&epoch),
             bundle: self.bundle.clone(),
-            simulator: self.simulator.clone(),
+            simulator: self.grant_simulator(),
+            audit: self.audit.clone(),
+            audit_sampler: self.audit_sampler.clone(),
+            quantization: self.quantization.clone(),
+            simulate_first: self.simulate_first.clone(),
+            inclusion: (!self.allow_multi_inclusion).then(|| self.inclusion.clone()),
+            max_gas: self.max_gas,
+            epoch_age: (self.min_epoch_age > 0).then(|| EpochAgeGuard {
+                min_age: self.min_epoch_age,
+                chain_map: self.chain_map.clone(),
+            }),
+            latency: self.latency.clone(),
+            in_flight: self.in_flight.clone(),
+            negotiated_version: Cell::new(SCHEMA_VERSION),
+            clamp_to_window: self.clamp_to_window,
+            compress_traces: self.compress_traces,
+            max_concurrent_calls: self.max_concurrent_calls,
+            simulate_timeout: self.simulate_timeout,
+            active_calls: self.active_calls.clone(),
+            call_budget: self.call_budget.clone(),
+            rate_limit: self.rate_limit.clone(),
+            guard_observer: self.guard_observer.clone(),
+            recent_results: RecentResults::default(),
+            attestor: self.attestor.clone(),
         };
         builder.set_bundle_access(new_client(server));
 
         Ok(())
     }
+
+    fn authenticate(
+        &self,
+        signer: Option<stem_capnp::signer::Client>,
+        epoch: &Epoch,
+    ) -> Promise<(), Error> {
+        if !self.verify_builder_auth {
+            return Promise::ok(());
+        }
+        let expected = pry!(BuilderKey::parse(&self.builder_pubkey));
+        let Some(signer) = signer else {
+            return Promise::err(builder_auth_failed("no signer supplied"));
+        };
+        let domain = self.challenge_domain(epoch);
+        let nonce = pry!(self.challenge_nonces.fresh());
+        let mut req = signer.sign_request();
+        req.get().set_domain(domain.as_str());
+        req.get().set_nonce(nonce);
+
+        Promise::from_future(async move {
+            let resp = req
+                .send()
+                .promise
+                .await
+                .map_err(|e| builder_auth_failed(&e.extra))?;
+            let sig = resp.get()?.get_sig()?;
+            let signer_key = recover_signer(&signer_message(&domain, nonce), sig)
+                .map_err(|e| builder_auth_failed(&e.extra))?;
+            if signer_key != expected {
+                return Err(builder_auth_failed(
+                    "signature does not recover to builder_pubkey",
+                ));
+            }
+            Ok(())
+        })
+    }
+}
+
+fn builder_auth_failed(detail: &str) -> Error {
+    MembraneError::BuilderAuthFailed {
+        detail: detail.to_string(),
+    }
+    .into()
+}
+
+/// Optional settings for [`bundle_membrane_with`].
+#[derive(Clone, Default)]
+pub struct MembraneOptions {
+    /// Sink recording issuance, simulate, include and revoke events.
+    pub audit: Option<Arc<dyn AuditSink>>,
+    /// Attests the grant and each simulate result; see
+    /// [`BundleGrantBuilder::attestor`].
+    pub attestor: Option<Arc<dyn Authenticator>>,
+    /// Token bucket on grafts; see [`BundleGrantBuilder::issuance_limit`].
+    pub issuance_limit: Option<RateLimitGuard>,
+    /// Guard to mint under, e.g. one restored by
+    /// [`GrantRegistry::guard`](crate::registry::GrantRegistry::guard)
+    /// after a restart. The returned handle then revokes a child of it, so
+    /// the restored grant's revocation still applies.
+    pub revocation: Option<RevocationGuard>,
 }
 
-/// Create a bundle-access membrane and return the revocation handle.
+/// Create a bundle-access membrane and return the searcher's handles.
 ///
-/// The caller retains the [`RevocationHandle`] and exposes the returned
-/// membrane client to the builder (e.g. over capnp-rpc TCP).
+/// The caller retains the [`RevocationHandle`] and [`BundleHandle`] and
+/// exposes the returned membrane client to the builder (e.g. over
+/// capnp-rpc TCP). Updates through the bundle handle apply to the
+/// builder's next simulation without a new graft.
 pub fn bundle_membrane(
     epoch_rx: watch::Receiver<Epoch>,
     bundle: BundleSpec,
@@ -62,17 +458,748 @@ pub fn bundle_membrane(
     simulator: Arc<dyn BundleSimulator>,
 ) -> (
     RevocationHandle,
+    BundleHandle,
     membrane_core::stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned>,
 ) {
-    let (handle, guard) = RevocationGuard::new();
-    let grant_builder = BundleGrantBuilder {
+    bundle_membrane_with(
+        epoch_rx,
         bundle,
         valid_from,
         valid_until,
         builder_pubkey,
         simulator,
-        revocation_guard: guard,
+        MembraneOptions::default(),
+    )
+}
+
+/// [`bundle_membrane`] with [`MembraneOptions`]. An audit sink records the
+/// revocation handle's revoke as well as the grant's own events.
+pub fn bundle_membrane_with(
+    epoch_rx: watch::Receiver<Epoch>,
+    bundle: BundleSpec,
+    valid_from: u64,
+    valid_until: u64,
+    builder_pubkey: Vec<u8>,
+    simulator: Arc<dyn BundleSimulator>,
+    options: MembraneOptions,
+) -> (
+    RevocationHandle,
+    BundleHandle,
+    membrane_core::stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned>,
+) {
+    let (handle, guard) = match &options.revocation {
+        Some(restored) => restored.derive_child(),
+        None => RevocationGuard::new(),
     };
+    let handle = match &options.audit {
+        Some(sink) => handle.with_audit(sink.clone()),
+        None => handle,
+    };
+    let bundle = BundleHandle::from(bundle);
+    let mut grant_builder = BundleGrantBuilder::new(
+        bundle.clone(),
+        valid_from,
+        valid_until,
+        builder_pubkey,
+        simulator,
+        guard,
+    );
+    grant_builder.audit = options.audit;
+    grant_builder.issuance_limit = options.issuance_limit;
+    grant_builder.attestor = options.attestor;
     let client = new_client(MembraneServer::new(epoch_rx, grant_builder));
-    (handle, client)
+    (handle, bundle, client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::SimResult;
+    use crate::audit::AuditTrail;
+    use crate::auth::{HashAlgo, HmacAuthenticator, KeySigner, SignatureAuthenticator};
+    use crate::contents::TxPolicy;
+    use crate::registry::GrantRegistry;
+    use crate::simulator::DryRunSimulator;
+    use k256::ecdsa::SigningKey;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct MockSimulator;
+
+    impl BundleSimulator for MockSimulator {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            _target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            Box::pin(async { Err(Error::unimplemented("mock".to_string())) })
+        }
+    }
+
+    fn test_epoch(adopted_block: u64) -> Epoch {
+        Epoch {
+            seq: 1,
+            head: vec![],
+            adopted_block,
+        }
+    }
+
+    fn test_builder(valid_from: u64, valid_until: u64) -> BundleGrantBuilder {
+        let (_handle, guard) = RevocationGuard::new();
+        let mut b = BundleGrantBuilder::new(
+            BundleSpec {
+                txs: vec![vec![0x01, 0x02]],
+            },
+            valid_from,
+            valid_until,
+            vec![0x11; 20],
+            Arc::new(MockSimulator),
+            guard,
+        );
+        b.past_window = PastWindowPolicy::Reject;
+        b
+    }
+
+    #[test]
+    fn sessions_share_one_result_cache() {
+        let mut b = test_builder(100, 110);
+        b.result_cache = Some(8);
+        let first = b.grant_simulator();
+        let second = b.grant_simulator();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        b.result_cache = None;
+        assert!(Arc::ptr_eq(&b.grant_simulator(), &b.simulator));
+    }
+
+    #[test]
+    fn window_in_past_is_rejected() {
+        let b = test_builder(100, 110);
+        let err = b.check_window_not_past(&test_epoch(111)).unwrap_err();
+        assert!(err.to_string().contains("windowInPast"));
+    }
+
+    #[test]
+    fn window_straddling_or_ahead_of_head_is_accepted() {
+        let b = test_builder(100, 110);
+        assert!(b.check_window_not_past(&test_epoch(105)).is_ok());
+        assert!(b.check_window_not_past(&test_epoch(110)).is_ok());
+        assert!(b.check_window_not_past(&test_epoch(50)).is_ok());
+    }
+
+    #[test]
+    fn window_in_past_only_warns_by_default() {
+        let mut b = test_builder(100, 110);
+        b.past_window = PastWindowPolicy::default();
+        assert!(b.check_window_not_past(&test_epoch(200)).is_ok());
+    }
+
+    #[test]
+    fn dry_run_reports_guard_failures() {
+        let b = test_builder(100, 110);
+        assert!(b.dry_run(105).is_ok());
+        let err = b.dry_run(111).unwrap_err();
+        assert!(err.to_string().contains("blockOutOfWindow"));
+
+        let mut bad_key = test_builder(100, 110);
+        bad_key.builder_pubkey = vec![0xff; 7];
+        let err = bad_key.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("invalidBuilderPubkey"));
+    }
+
+    #[test]
+    fn dry_run_sees_spent_budget_and_elapsed_time_window() {
+        let mut b = test_builder(100, 110);
+        b.call_budget = Some(CallBudgetGuard::new(1));
+        assert!(b.dry_run(105).is_ok());
+        b.call_budget.as_ref().unwrap().consume().unwrap();
+        let err = b.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("callBudgetExhausted"));
+
+        let b = test_builder(100, 110);
+        b.time_window
+            .set(TimeWindowGuard {
+                valid_until: std::time::Instant::now() - Duration::from_secs(1),
+            })
+            .unwrap();
+        let err = b.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("grantExpired"));
+    }
+
+    #[test]
+    fn dry_run_sees_revocation() {
+        let (handle, guard) = RevocationGuard::new();
+        let mut b = test_builder(100, 110);
+        b.revocation_guard = guard;
+        handle.revoke();
+        let err = b.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("revoked"));
+    }
+
+    #[test]
+    fn dry_run_rejects_disallowed_tx_type() {
+        let mut b = test_builder(100, 110);
+        let bundle = BundleSpec {
+            txs: vec![vec![0x02, 0xf8], vec![0x03, 0xf8]],
+        };
+        let policy = |allowed: &[u8]| TxPolicy {
+            allowed_tx_types: Some(allowed.iter().copied().collect()),
+            ..TxPolicy::default()
+        };
+        b.bundle = BundleHandle::new(bundle.clone(), None).with_policy(policy(&[0, 2]));
+        let err = b.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("disallowedTxType"));
+
+        b.bundle = BundleHandle::new(bundle, None).with_policy(policy(&[0, 2, 3]));
+        assert!(b.dry_run(105).is_ok());
+    }
+
+    #[test]
+    fn replay_protection_policy_rejects_pre_eip155_legacy_tx() {
+        use crate::rlp::tests::legacy_tx;
+
+        let mut b = test_builder(100, 110);
+        let unprotected = BundleSpec {
+            txs: vec![legacy_tx(37), legacy_tx(28)],
+        };
+        let policy = TxPolicy {
+            reject_non_replay_protected: true,
+            ..TxPolicy::default()
+        };
+        b.bundle = unprotected.clone().into();
+        assert!(b.dry_run(105).is_ok()); // accepted by default
+
+        b.bundle = BundleHandle::new(unprotected, None).with_policy(policy.clone());
+        let err = b.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("noReplayProtection: tx 1"));
+
+        let protected = BundleSpec {
+            txs: vec![legacy_tx(37), vec![0x02, 0xf8]],
+        };
+        b.bundle = BundleHandle::new(protected, None).with_policy(policy);
+        assert!(b.dry_run(105).is_ok());
+    }
+
+    #[test]
+    fn dry_run_checks_disjoint_windows() {
+        let mut b = test_builder(100, 110);
+        b.windows = Some(vec![(100, 101), (108, 110)]);
+        assert!(b.dry_run(101).is_ok());
+        assert!(b.dry_run(109).is_ok());
+        let err = b.dry_run(105).unwrap_err();
+        assert!(err.to_string().contains("[100, 101], [108, 110]"));
+    }
+
+    async fn graft_with(
+        mut b: BundleGrantBuilder,
+        key: &SigningKey,
+        signer: Option<SigningKey>,
+    ) -> Result<(), Error> {
+        b.builder_pubkey =
+            BuilderKey::PublicKey(k256::PublicKey::from(key.verifying_key())).to_bytes();
+        b.verify_builder_auth = true;
+        b.challenge = b"grant-42".to_vec();
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, b));
+        let mut req = membrane.graft_request();
+        if let Some(sk) = signer {
+            req.get().set_signer(new_client(KeySigner::new(sk)));
+        }
+        let resp = req.send().promise.await?;
+        resp.get()?
+            .get_session()?
+            .get_extension()?
+            .get_bundle_access()?;
+        Ok(())
+    }
+
+    async fn graft(
+        membrane: &stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned>,
+    ) -> Result<bundle_capnp::bundle_access::Client, Error> {
+        let resp = membrane.graft_request().send().promise.await?;
+        resp.get()?
+            .get_session()?
+            .get_extension()?
+            .get_bundle_access()
+    }
+
+    async fn simulate(access: &bundle_capnp::bundle_access::Client) -> Result<(), Error> {
+        let mut req = access.simulate_request();
+        req.get().set_target_block(105);
+        req.send().promise.await.map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn revoke_through_membrane_handle_is_audited() {
+        let path =
+            std::env::temp_dir().join(format!("membrane-grant-revoke-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let trail = Arc::new(AuditTrail::open(&path).unwrap());
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let (handle, _bundle, membrane) = bundle_membrane_with(
+            rx,
+            BundleSpec {
+                txs: vec![vec![0x01]],
+            },
+            100,
+            110,
+            vec![0x11; 20],
+            Arc::new(MockSimulator),
+            MembraneOptions {
+                audit: Some(trail.clone()),
+                ..Default::default()
+            },
+        );
+        graft(&membrane).await.unwrap();
+        handle.revoke();
+
+        trail.verify().unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<&str> = log
+            .lines()
+            .map(|line| line.splitn(3, ' ').nth(2).unwrap())
+            .collect();
+        assert!(events[0].starts_with("grantIssued"));
+        assert_eq!(events.last(), Some(&"revoke"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn revocation_restored_after_restart_refuses_grafted_session() {
+        let registry = GrantRegistry::new();
+        let (handle, _guard) = RevocationGuard::new();
+        let id = registry.register(handle);
+        registry.revoke(id);
+        let restored = GrantRegistry::load(&registry.dump()).unwrap();
+
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let (handle, _bundle, membrane) = bundle_membrane_with(
+            rx,
+            BundleSpec {
+                txs: vec![vec![0x01]],
+            },
+            100,
+            110,
+            vec![0x11; 20],
+            Arc::new(DryRunSimulator::default()),
+            MembraneOptions {
+                revocation: restored.guard(id),
+                ..Default::default()
+            },
+        );
+        assert!(!handle.is_revoked());
+        let access = graft(&membrane).await.unwrap();
+        let err = simulate(&access).await.unwrap_err();
+        assert!(err.to_string().contains("revoked"));
+    }
+
+    #[tokio::test]
+    async fn hmac_attested_grant_and_result_verify_with_shared_key() {
+        let mut b = test_builder(100, 110);
+        b.attestor = Some(Arc::new(HmacAuthenticator::new(b"shared".to_vec())));
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, b));
+        let resp = membrane.graft_request().send().promise.await.unwrap();
+        let grant = resp
+            .get()
+            .unwrap()
+            .get_session()
+            .unwrap()
+            .get_extension()
+            .unwrap();
+
+        let msg = grant_message(grant.get_builder_pubkey().unwrap(), 100, 110);
+        let tag = grant.get_attestation().unwrap();
+        assert!(HmacAuthenticator::new(b"shared".to_vec())
+            .verify(&msg, tag)
+            .is_ok());
+        let err = HmacAuthenticator::new(b"wrong".to_vec())
+            .verify(&msg, tag)
+            .unwrap_err();
+        assert!(err.to_string().contains("authFailed"));
+
+        let access = grant.get_bundle_access().unwrap();
+        let mut req = access.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        assert!(HmacAuthenticator::new(b"shared".to_vec())
+            .verify(r.get_result_hash().unwrap(), r.get_attestation().unwrap())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn sha256_attestation_verifies_only_under_sha256() {
+        let sk = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let searcher = BuilderKey::PublicKey(k256::PublicKey::from(sk.verifying_key()));
+        let mut b = test_builder(100, 110);
+        b.attestor = Some(Arc::new(
+            SignatureAuthenticator::signer(sk).with_hash(HashAlgo::Sha256),
+        ));
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, b));
+        let resp = membrane.graft_request().send().promise.await.unwrap();
+        let grant = resp
+            .get()
+            .unwrap()
+            .get_session()
+            .unwrap()
+            .get_extension()
+            .unwrap();
+
+        let msg = grant_message(grant.get_builder_pubkey().unwrap(), 100, 110);
+        let tag = grant.get_attestation().unwrap();
+        let sha = SignatureAuthenticator::verifier(searcher.clone()).with_hash(HashAlgo::Sha256);
+        assert!(sha.verify(&msg, tag).is_ok());
+        assert!(SignatureAuthenticator::verifier(searcher)
+            .verify(&msg, tag)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn unattested_grant_has_empty_attestation() {
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, test_builder(100, 110)));
+        let resp = membrane.graft_request().send().promise.await.unwrap();
+        let grant = resp
+            .get()
+            .unwrap()
+            .get_session()
+            .unwrap()
+            .get_extension()
+            .unwrap();
+        assert!(grant.get_attestation().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrency_cap_spans_every_session_of_the_grant() {
+        let mut b = test_builder(100, 110);
+        b.simulator = Arc::new(DryRunSimulator::default());
+        b.max_concurrent_calls = Some(1);
+        let active = b.active_calls.clone();
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, b));
+
+        let first = graft(&membrane).await.unwrap();
+        simulate(&first).await.unwrap();
+
+        // A simulation still running on the first session...
+        let permit = active.enter();
+        // ...is not forgotten by a second graft.
+        let second = graft(&membrane).await.unwrap();
+        let err = simulate(&second).await.unwrap_err();
+        assert!(err.to_string().contains("tooManyConcurrentCalls"));
+
+        drop(permit);
+        simulate(&second).await.unwrap();
+        assert_eq!(active.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn later_graft_does_not_restart_the_time_window() {
+        let mut b = test_builder(100, 110);
+        b.simulator = Arc::new(DryRunSimulator::default());
+        b.valid_for = Some(Duration::from_millis(50));
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, b));
+
+        let first = graft(&membrane).await.unwrap();
+        simulate(&first).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let second = graft(&membrane).await.unwrap();
+        let err = simulate(&second).await.unwrap_err();
+        assert!(err.to_string().contains("grantExpired"));
+    }
+
+    /// Head at a settable block, hashed as its big-endian bytes.
+    struct SettableHead(std::sync::atomic::AtomicU64);
+
+    impl EpochChainMap for SettableHead {
+        fn head_block(&self, _epoch: &Epoch) -> u64 {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+
+        fn head_hash(&self, epoch: &Epoch) -> Option<Vec<u8>> {
+            Some(self.head_block(epoch).to_be_bytes().to_vec())
+        }
+    }
+
+    /// Records the state of every pinned simulation.
+    #[derive(Default)]
+    struct PinRecorder(std::sync::Mutex<Vec<PinnedState>>);
+
+    impl BundleSimulator for PinRecorder {
+        fn simulate(
+            &self,
+            _bundle: &BundleSpec,
+            _target_block: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            Box::pin(async { Err(Error::failed("unpinned".to_string())) })
+        }
+
+        fn simulate_pinned(
+            &self,
+            _bundle: &BundleSpec,
+            state: &PinnedState,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>>
+        {
+            self.0.lock().unwrap().push(state.clone());
+            Box::pin(async { Ok(SimResult::default()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn state_is_pinned_by_hash_once_per_grant() {
+        let recorder = Arc::new(PinRecorder::default());
+        let head = Arc::new(SettableHead(std::sync::atomic::AtomicU64::new(100)));
+        let mut b = test_builder(100, 110);
+        b.simulator = recorder.clone();
+        b.chain_map = head.clone();
+        b.pin_state_at_issuance = true;
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, b));
+
+        let first = graft(&membrane).await.unwrap();
+        head.0.store(103, std::sync::atomic::Ordering::SeqCst);
+        let second = graft(&membrane).await.unwrap();
+        simulate(&first).await.unwrap();
+        simulate(&second).await.unwrap();
+
+        let pinned = PinnedState {
+            block: 100,
+            hash: Some(100u64.to_be_bytes().to_vec()),
+        };
+        assert_eq!(*recorder.0.lock().unwrap(), vec![pinned.clone(), pinned]);
+    }
+
+    #[tokio::test]
+    async fn issuance_beyond_rate_is_rejected_until_refill() {
+        tokio::time::pause();
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let (_handle, _bundle, membrane) = bundle_membrane_with(
+            rx,
+            BundleSpec {
+                txs: vec![vec![0x01]],
+            },
+            100,
+            110,
+            vec![0x11; 20],
+            Arc::new(MockSimulator),
+            MembraneOptions {
+                issuance_limit: Some(RateLimitGuard::new(20.0, 2)),
+                ..Default::default()
+            },
+        );
+
+        graft(&membrane).await.unwrap();
+        graft(&membrane).await.unwrap();
+        let err = graft(&membrane).await.unwrap_err();
+        assert!(err.to_string().contains("issuanceRateLimited"));
+
+        // One token refills every 50ms.
+        tokio::time::advance(std::time::Duration::from_millis(40)).await;
+        assert!(graft(&membrane).await.is_err());
+        tokio::time::advance(std::time::Duration::from_millis(20)).await;
+        graft(&membrane).await.unwrap();
+    }
+
+    /// Counts the grants it attests.
+    #[derive(Default)]
+    struct CountingAttestor(std::sync::atomic::AtomicUsize);
+
+    impl Authenticator for CountingAttestor {
+        fn sign(&self, _message: &[u8]) -> Result<Vec<u8>, Error> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(vec![0xaa])
+        }
+
+        fn verify(&self, _message: &[u8], _tag: &[u8]) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_graft_is_not_attested() {
+        let attestor = Arc::new(CountingAttestor::default());
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let (_handle, _bundle, membrane) = bundle_membrane_with(
+            rx,
+            BundleSpec {
+                txs: vec![vec![0x01]],
+            },
+            100,
+            110,
+            vec![0x11; 20],
+            Arc::new(MockSimulator),
+            MembraneOptions {
+                attestor: Some(attestor.clone()),
+                issuance_limit: Some(RateLimitGuard::new(0.0, 1)),
+                ..Default::default()
+            },
+        );
+
+        graft(&membrane).await.unwrap();
+        let err = graft(&membrane).await.unwrap_err();
+        assert!(err.to_string().contains("issuanceRateLimited"));
+        assert_eq!(attestor.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    /// Signs challenges with `auth`, recording every signature it makes.
+    struct RecordingSigner {
+        auth: SignatureAuthenticator,
+        signed: Rc<RefCell<Vec<(u64, Vec<u8>)>>>,
+    }
+
+    #[allow(refining_impl_trait)]
+    impl stem_capnp::signer::Server for RecordingSigner {
+        fn sign(
+            self: capnp::capability::Rc<Self>,
+            params: stem_capnp::signer::SignParams,
+            mut results: stem_capnp::signer::SignResults,
+        ) -> Promise<(), Error> {
+            let params = pry!(params.get());
+            let domain = pry!(pry!(params.get_domain()).to_str());
+            let nonce = params.get_nonce();
+            let sig = pry!(self.auth.sign(&signer_message(domain, nonce)));
+            self.signed.borrow_mut().push((nonce, sig.clone()));
+            results.get().set_sig(&sig);
+            Promise::ok(())
+        }
+    }
+
+    /// Answers every challenge with the same captured signature.
+    struct ReplayingSigner {
+        sig: Vec<u8>,
+    }
+
+    #[allow(refining_impl_trait)]
+    impl stem_capnp::signer::Server for ReplayingSigner {
+        fn sign(
+            self: capnp::capability::Rc<Self>,
+            _params: stem_capnp::signer::SignParams,
+            mut results: stem_capnp::signer::SignResults,
+        ) -> Promise<(), Error> {
+            results.get().set_sig(&self.sig);
+            Promise::ok(())
+        }
+    }
+
+    /// Signs correctly, but the epoch advances while it does.
+    struct SlowSigner {
+        auth: SignatureAuthenticator,
+        epoch_tx: watch::Sender<Epoch>,
+    }
+
+    #[allow(refining_impl_trait)]
+    impl stem_capnp::signer::Server for SlowSigner {
+        fn sign(
+            self: capnp::capability::Rc<Self>,
+            params: stem_capnp::signer::SignParams,
+            mut results: stem_capnp::signer::SignResults,
+        ) -> Promise<(), Error> {
+            let params = pry!(params.get());
+            let domain = pry!(pry!(params.get_domain()).to_str());
+            let sig = pry!(self.auth.sign(&signer_message(domain, params.get_nonce())));
+            self.epoch_tx.send_modify(|e| e.seq += 1);
+            results.get().set_sig(&sig);
+            Promise::ok(())
+        }
+    }
+
+    fn auth_builder(key: &SigningKey) -> BundleGrantBuilder {
+        let mut b = test_builder(100, 110);
+        b.builder_pubkey =
+            BuilderKey::PublicKey(k256::PublicKey::from(key.verifying_key())).to_bytes();
+        b.verify_builder_auth = true;
+        b
+    }
+
+    async fn graft_signed_by(
+        membrane: &stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned>,
+        signer: stem_capnp::signer::Client,
+    ) -> Result<(), Error> {
+        let mut req = membrane.graft_request();
+        req.get().set_signer(signer);
+        let resp = req.send().promise.await?;
+        resp.get()?
+            .get_session()?
+            .get_extension()?
+            .get_bundle_access()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn captured_signature_cannot_be_replayed() {
+        let key = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let (_tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, auth_builder(&key)));
+
+        let signed = Rc::new(RefCell::new(Vec::new()));
+        for _ in 0..2 {
+            let signer = new_client(RecordingSigner {
+                auth: SignatureAuthenticator::signer(key.clone()),
+                signed: signed.clone(),
+            });
+            graft_signed_by(&membrane, signer).await.unwrap();
+        }
+        let signed = signed.borrow().clone();
+        assert_ne!(signed[0].0, signed[1].0, "each graft gets a fresh nonce");
+
+        // Same epoch, same challenge: only the nonce differs.
+        let replay = new_client(ReplayingSigner {
+            sig: signed[0].1.clone(),
+        });
+        let err = graft_signed_by(&membrane, replay).await.unwrap_err();
+        assert!(err.to_string().contains("builderAuthFailed"));
+    }
+
+    #[tokio::test]
+    async fn epoch_advancing_during_auth_fails_the_graft() {
+        let key = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let (tx, rx) = watch::channel(test_epoch(100));
+        let membrane: stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> =
+            new_client(MembraneServer::new(rx, auth_builder(&key)));
+
+        let signer = new_client(SlowSigner {
+            auth: SignatureAuthenticator::signer(key.clone()),
+            epoch_tx: tx,
+        });
+        let err = graft_signed_by(&membrane, signer).await.unwrap_err();
+        assert!(err.to_string().contains("staleEpoch"));
+
+        // Under the new epoch, a prompt signer gets through.
+        graft_signed_by(&membrane, new_client(KeySigner::new(key)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn graft_requires_signature_from_builder_key() {
+        let key = SigningKey::from_slice(&[0x22; 32]).unwrap();
+        graft_with(test_builder(100, 110), &key, Some(key.clone()))
+            .await
+            .unwrap();
+
+        let impostor = SigningKey::from_slice(&[0x33; 32]).unwrap();
+        let err = graft_with(test_builder(100, 110), &key, Some(impostor))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("builderAuthFailed"));
+
+        let err = graft_with(test_builder(100, 110), &key, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("builderAuthFailed"));
+    }
 }
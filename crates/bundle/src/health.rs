@@ -0,0 +1,79 @@
+//! Cheap liveness probe for the membrane host.
+//!
+//! [`HealthServer`] answers `Health.ping` without grafting a session or
+//! calling the simulator. Backend health is cached in a [`BackendHealth`]
+//! flag that the operator's own checks keep up to date, so a probe costs
+//! one atomic load however often the load balancer asks.
+
+use crate::bundle_capnp;
+use capnp::capability::Promise;
+use capnp::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Last known reachability of the simulation backend. Starts healthy.
+#[derive(Clone, Debug)]
+pub struct BackendHealth {
+    healthy: Arc<AtomicBool>,
+}
+
+impl Default for BackendHealth {
+    fn default() -> Self {
+        Self {
+            healthy: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl BackendHealth {
+    /// Record the outcome of a backend health check.
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Release);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+}
+
+/// Serves the `Health` capability.
+pub struct HealthServer {
+    pub backend: BackendHealth,
+}
+
+#[allow(refining_impl_trait)]
+impl bundle_capnp::health::Server for HealthServer {
+    fn ping(
+        self: capnp::capability::Rc<Self>,
+        _: bundle_capnp::health::PingParams,
+        mut results: bundle_capnp::health::PingResults,
+    ) -> Promise<(), Error> {
+        let mut r = results.get();
+        r.set_alive(true);
+        r.set_backend_healthy(self.backend.is_healthy());
+        Promise::ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ping(client: &bundle_capnp::health::Client) -> (bool, bool) {
+        let resp = client.ping_request().send().promise.await.unwrap();
+        let r = resp.get().unwrap();
+        (r.get_alive(), r.get_backend_healthy())
+    }
+
+    #[tokio::test]
+    async fn ping_reports_alive_and_cached_backend_health() {
+        let backend = BackendHealth::default();
+        let client: bundle_capnp::health::Client = capnp_rpc::new_client(HealthServer {
+            backend: backend.clone(),
+        });
+        assert_eq!(ping(&client).await, (true, true));
+
+        backend.set_healthy(false);
+        assert_eq!(ping(&client).await, (true, false));
+    }
+}
@@ -0,0 +1,95 @@
+//! Simulate latency tracking for SLA reporting.
+//!
+//! A [`LatencyTracker`] is a shared histogram of simulate latencies. Hand the
+//! same tracker to several grants for a global view, or one per grant.
+
+use hdrhistogram::Histogram;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Highest latency tracked precisely; slower calls saturate at this value.
+const MAX_TRACKED_MICROS: u64 = 60_000_000;
+
+/// Shared histogram of simulate latencies, in microseconds.
+#[derive(Clone)]
+pub struct LatencyTracker {
+    histogram: Arc<Mutex<Histogram<u64>>>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        let histogram = Histogram::new_with_bounds(1, MAX_TRACKED_MICROS, 3)
+            .expect("static histogram bounds are valid");
+        Self {
+            histogram: Arc::new(Mutex::new(histogram)),
+        }
+    }
+
+    /// Record one call's latency.
+    pub fn record(&self, latency: Duration) {
+        let micros = (latency.as_micros() as u64).max(1);
+        self.histogram.lock().unwrap().saturating_record(micros);
+    }
+
+    /// Latency at quantile `q` (e.g. `0.99`), or `None` before any record.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let h = self.histogram.lock().unwrap();
+        if h.is_empty() {
+            return None;
+        }
+        Some(Duration::from_micros(h.value_at_quantile(q)))
+    }
+
+    pub fn p50(&self) -> Option<Duration> {
+        self.quantile(0.5)
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.quantile(0.99)
+    }
+
+    /// Number of calls recorded.
+    pub fn count(&self) -> u64 {
+        self.histogram.lock().unwrap().len()
+    }
+}
+
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Duration, expected_ms: u64) {
+        let expected = Duration::from_millis(expected_ms).as_micros() as f64;
+        let err = (actual.as_micros() as f64 - expected).abs() / expected;
+        assert!(
+            err < 0.01,
+            "{:?} not within 1% of {}ms",
+            actual,
+            expected_ms
+        );
+    }
+
+    #[test]
+    fn empty_tracker_reports_nothing() {
+        let t = LatencyTracker::new();
+        assert_eq!(t.count(), 0);
+        assert!(t.p50().is_none());
+    }
+
+    #[test]
+    fn known_latencies_give_expected_percentiles() {
+        let t = LatencyTracker::new();
+        for ms in 1..=100 {
+            t.record(Duration::from_millis(ms));
+        }
+        assert_eq!(t.count(), 100);
+        assert_close(t.p50().unwrap(), 50);
+        assert_close(t.p99().unwrap(), 99);
+    }
+}
@@ -11,8 +11,38 @@ pub mod bundle_capnp {
 
 pub mod revocation;
 pub mod access;
+pub mod audit;
+pub mod auth;
+pub mod cache;
+pub mod contents;
+pub mod error;
 pub mod grant;
+pub mod health;
+pub mod latency;
+pub mod pubkey;
+pub mod registry;
+pub mod simulator;
+pub mod status;
+mod rlp;
 
 pub use revocation::{RevocationGuard, RevocationHandle};
-pub use access::{BundleAccessServer, BundleSimulator, BlockWindowGuard, BundleSpec, SimResult};
-pub use grant::BundleGrantBuilder;
+pub use audit::{AuditEvent, AuditSampler, AuditSink, AuditTrail};
+pub use auth::{
+    Authenticator, ChallengeNonces, HashAlgo, HmacAuthenticator, SignatureAuthenticator,
+    grant_message,
+};
+pub use access::{
+    BundleAccessServer, BundleSimulator, BlockWindowGuard, BundleSpec, CallBudgetGuard,
+    EpochAgeGuard, GuardObserver, InclusionGuard, Log, NoopObserver, PinnedState, RateLimitGuard,
+    RecentResults, ResultFields, ResultQuantization, SimResult, SimulateFirstGuard, SimulatorInfo,
+    TimeWindowGuard, TracingObserver, TxResult, decompress_trace, tx_type,
+};
+pub use cache::CachingSimulator;
+pub use contents::{BundleHandle, TxPolicy};
+pub use grant::{BundleGrantBuilder, MembraneOptions, PastWindowPolicy};
+pub use health::{BackendHealth, HealthServer};
+pub use latency::LatencyTracker;
+pub use pubkey::BuilderKey;
+pub use registry::{GrantRegistry, InFlightTracker};
+pub use simulator::DryRunSimulator;
+pub use status::watch_epoch_status;
@@ -0,0 +1,116 @@
+//! Builder public key normalization.
+//!
+//! Builder tooling hands over keys in several shapes: 33-byte compressed
+//! secp256k1, 65-byte uncompressed secp256k1, or a bare 20-byte Ethereum
+//! address. [`BuilderKey::parse`] accepts all three, rejects anything else,
+//! and canonicalizes so grants can be stored and compared by one identity.
+
+use capnp::Error;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::PublicKey;
+use sha3::{Digest, Keccak256};
+
+/// A builder identity parsed from any supported key encoding.
+#[derive(Clone, Debug)]
+pub enum BuilderKey {
+    /// Full secp256k1 public key; canonical form is 33-byte compressed.
+    PublicKey(PublicKey),
+    /// Ethereum address only; the public key itself is unknown.
+    Address([u8; 20]),
+}
+
+impl BuilderKey {
+    /// Parse a compressed (33), uncompressed (65) or address (20) encoding.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.len() {
+            20 => {
+                let mut addr = [0u8; 20];
+                addr.copy_from_slice(bytes);
+                Ok(Self::Address(addr))
+            }
+            33 | 65 => PublicKey::from_sec1_bytes(bytes)
+                .map(Self::PublicKey)
+                .map_err(|_| {
+                    Error::failed("invalidBuilderPubkey: not a valid secp256k1 point".to_string())
+                }),
+            n => Err(Error::failed(format!(
+                "invalidBuilderPubkey: unsupported length {}",
+                n
+            ))),
+        }
+    }
+
+    /// Ethereum address of this builder: the identity used for comparison.
+    pub fn address(&self) -> [u8; 20] {
+        match self {
+            Self::PublicKey(pk) => pubkey_address(pk),
+            Self::Address(addr) => *addr,
+        }
+    }
+
+    /// Canonical encoding for storage: compressed key, or the address when
+    /// only the address is known.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::PublicKey(pk) => pk.to_encoded_point(true).as_bytes().to_vec(),
+            Self::Address(addr) => addr.to_vec(),
+        }
+    }
+}
+
+impl PartialEq for BuilderKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Eq for BuilderKey {}
+
+/// Derive the Ethereum address of a secp256k1 public key.
+pub(crate) fn pubkey_address(pk: &PublicKey) -> [u8; 20] {
+    let point = pk.to_encoded_point(false);
+    let hash = keccak256(&point.as_bytes()[1..]);
+    let mut addr = [0u8; 20];
+    addr.copy_from_slice(&hash[12..]);
+    addr
+}
+
+pub(crate) fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::SecretKey;
+
+    fn test_key() -> PublicKey {
+        SecretKey::from_slice(&[0x11; 32]).unwrap().public_key()
+    }
+
+    #[test]
+    fn all_encodings_normalize_to_same_identity() {
+        let pk = test_key();
+        let compressed = pk.to_encoded_point(true).as_bytes().to_vec();
+        let uncompressed = pk.to_encoded_point(false).as_bytes().to_vec();
+        let address = pubkey_address(&pk).to_vec();
+
+        let a = BuilderKey::parse(&compressed).unwrap();
+        let b = BuilderKey::parse(&uncompressed).unwrap();
+        let c = BuilderKey::parse(&address).unwrap();
+
+        assert_eq!(a.to_bytes(), compressed);
+        assert_eq!(b.to_bytes(), compressed);
+        assert_eq!(c.to_bytes(), address);
+        assert_eq!(a, b);
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        for bad in [vec![], vec![0u8; 32], vec![0xff; 33], vec![0x04; 65]] {
+            let err = BuilderKey::parse(&bad).unwrap_err();
+            assert!(err.to_string().contains("invalidBuilderPubkey"));
+        }
+    }
+}
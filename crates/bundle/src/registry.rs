@@ -0,0 +1,316 @@
+//! Registry of issued grants, for bulk revocation on shutdown.
+//!
+//! The host registers each grant's [`RevocationHandle`] and hands the
+//! registry's [`InFlightTracker`] to its grant builders. On shutdown,
+//! [`GrantRegistry::shutdown`] revokes every grant so builders fail fast
+//! instead of seeing connection errors, then waits for in-flight
+//! simulations to drain:
+//!
+//! ```ignore
+//! let registry = GrantRegistry::new();
+//! let (handle, _bundle, client) = bundle_membrane(/* ... */);
+//! registry.register(handle);
+//!
+//! tokio::signal::ctrl_c().await?;
+//! registry.shutdown().await;
+//! ```
+//!
+//! Revocations can outlive the process: [`GrantRegistry::dump`] snapshots
+//! the revoked grants, and a registry rebuilt with [`GrantRegistry::load`]
+//! hands out their guards already revoked via [`GrantRegistry::guard`].
+
+use crate::error::MembraneError;
+use crate::revocation::{RevocationGuard, RevocationHandle};
+use capnp::Error;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Counts calls in progress so shutdown can wait for them.
+#[derive(Clone, Default)]
+pub struct InFlightTracker {
+    inner: Arc<InFlightInner>,
+}
+
+#[derive(Default)]
+struct InFlightInner {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Held for the duration of one call; dropping it marks the call done.
+pub struct InFlightPermit {
+    inner: Arc<InFlightInner>,
+}
+
+impl InFlightTracker {
+    /// Mark a call as started.
+    pub fn enter(&self) -> InFlightPermit {
+        self.inner.count.fetch_add(1, Ordering::AcqRel);
+        InFlightPermit {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Number of calls in progress.
+    pub fn count(&self) -> usize {
+        self.inner.count.load(Ordering::Acquire)
+    }
+
+    /// Wait until no calls are in progress.
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.inner.idle.notified();
+            if self.count() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        if self.inner.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+/// Issued grants, keyed by a registry-assigned grant id.
+#[derive(Default)]
+pub struct GrantRegistry {
+    grants: Mutex<HashMap<u64, RevocationHandle>>,
+    next_id: AtomicU64,
+    in_flight: InFlightTracker,
+}
+
+impl GrantRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take ownership of a grant's revocation handle; returns its grant id.
+    pub fn register(&self, handle: RevocationHandle) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.grants.lock().unwrap().insert(id, handle);
+        id
+    }
+
+    /// Revoke a single grant. Returns `false` if the id is unknown.
+    pub fn revoke(&self, grant_id: u64) -> bool {
+        match self.grants.lock().unwrap().get(&grant_id) {
+            Some(handle) => {
+                handle.revoke();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Guard for a registered grant, e.g. to re-mint its capability after
+    /// a restart. `None` if the id is unknown.
+    pub fn guard(&self, grant_id: u64) -> Option<RevocationGuard> {
+        self.grants
+            .lock()
+            .unwrap()
+            .get(&grant_id)
+            .map(RevocationHandle::guard)
+    }
+
+    /// Snapshot the revoked grants and the id counter.
+    ///
+    /// Layout: next id (u64), then per revoked grant its id (u64), reason
+    /// length (u32) and UTF-8 reason, all big-endian. Unrevoked grants are
+    /// not persisted. A reason length of `u32::MAX` means no recorded
+    /// reason.
+    pub fn dump(&self) -> Vec<u8> {
+        let mut out = self.next_id.load(Ordering::Relaxed).to_be_bytes().to_vec();
+        let grants = self.grants.lock().unwrap();
+        let mut revoked: Vec<_> = grants.iter().filter(|(_, h)| h.is_revoked()).collect();
+        revoked.sort_by_key(|(id, _)| **id);
+        for (id, handle) in revoked {
+            out.extend_from_slice(&id.to_be_bytes());
+            match handle.reason() {
+                Some(reason) => {
+                    out.extend_from_slice(&(reason.len() as u32).to_be_bytes());
+                    out.extend_from_slice(reason.as_bytes());
+                }
+                None => out.extend_from_slice(&u32::MAX.to_be_bytes()),
+            }
+        }
+        out
+    }
+
+    /// Rebuild a registry from a [`dump`](Self::dump): every grant in the
+    /// snapshot is registered under its old id, already revoked, and new
+    /// ids continue after the snapshot's.
+    pub fn load(bytes: &[u8]) -> Result<Self, Error> {
+        let mut rest = bytes;
+        let next_id = u64::from_be_bytes(take(&mut rest)?);
+        let mut grants = HashMap::new();
+        while !rest.is_empty() {
+            let id = u64::from_be_bytes(take(&mut rest)?);
+            let len = u32::from_be_bytes(take(&mut rest)?);
+            let (handle, _guard) = RevocationGuard::new();
+            if len == u32::MAX {
+                handle.revoke();
+            } else {
+                let len = len as usize;
+                if rest.len() < len {
+                    return Err(truncated());
+                }
+                let reason = std::str::from_utf8(&rest[..len])
+                    .map_err(|_| invalid_snapshot("reason is not UTF-8"))?;
+                handle.revoke_with_reason(reason.to_string());
+                rest = &rest[len..];
+            }
+            grants.insert(id, handle);
+        }
+        Ok(Self {
+            grants: Mutex::new(grants),
+            next_id: AtomicU64::new(next_id),
+            in_flight: InFlightTracker::default(),
+        })
+    }
+
+    /// Number of registered grants.
+    pub fn len(&self) -> usize {
+        self.grants.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Tracker to pass to grant builders so shutdown can drain their calls.
+    pub fn in_flight(&self) -> InFlightTracker {
+        self.in_flight.clone()
+    }
+
+    /// Revoke every registered grant, then wait for in-flight calls to finish.
+    ///
+    /// Builders see `revoked: serverShutdown`.
+    pub async fn shutdown(&self) {
+        for handle in self.grants.lock().unwrap().values() {
+            handle.revoke_with_reason("serverShutdown".to_string());
+        }
+        self.in_flight.wait_idle().await;
+    }
+}
+
+/// Split a fixed-size field off the front of a snapshot.
+fn take<const N: usize>(rest: &mut &[u8]) -> Result<[u8; N], Error> {
+    if rest.len() < N {
+        return Err(truncated());
+    }
+    let (head, tail) = rest.split_at(N);
+    *rest = tail;
+    Ok(head.try_into().unwrap())
+}
+
+fn truncated() -> Error {
+    invalid_snapshot("truncated")
+}
+
+fn invalid_snapshot(detail: &str) -> Error {
+    MembraneError::InvalidSnapshot {
+        detail: detail.to_string(),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_revokes_all_grants() {
+        let registry = GrantRegistry::new();
+        let (h1, g1) = RevocationGuard::new();
+        let (h2, g2) = RevocationGuard::new();
+        registry.register(h1);
+        registry.register(h2);
+        assert_eq!(registry.len(), 2);
+
+        registry.shutdown().await;
+        assert!(g1.check().is_err());
+        let err = g2.check().unwrap_err();
+        assert!(err.to_string().contains("serverShutdown"));
+    }
+
+    #[tokio::test]
+    async fn revoke_by_id_affects_only_that_grant() {
+        let registry = GrantRegistry::new();
+        let (h1, g1) = RevocationGuard::new();
+        let (h2, g2) = RevocationGuard::new();
+        let id1 = registry.register(h1);
+        registry.register(h2);
+
+        assert!(registry.revoke(id1));
+        assert!(!registry.revoke(99));
+        assert!(g1.check().is_err());
+        assert!(g2.check().is_ok());
+    }
+
+    #[test]
+    fn revocations_survive_dump_and_reload() {
+        let registry = GrantRegistry::new();
+        let (h1, _g1) = RevocationGuard::new();
+        let (h2, _g2) = RevocationGuard::new();
+        let (h3, _g3) = RevocationGuard::new();
+        let id1 = registry.register(h1);
+        let id2 = registry.register(h2);
+        let id3 = registry.register(h3);
+        registry.revoke(id1);
+        registry.grants.lock().unwrap()[&id3].revoke_with_reason("bundle landed".to_string());
+
+        let snapshot = registry.dump();
+        drop(registry);
+
+        let restored = GrantRegistry::load(&snapshot).unwrap();
+        assert!(restored.guard(id1).unwrap().check().is_err());
+        let err = restored.guard(id3).unwrap().check().unwrap_err();
+        assert_eq!(err.extra, "revoked: bundle landed");
+        // Unrevoked grants are re-issued rather than restored.
+        assert!(restored.guard(id2).is_none());
+        let (h4, _g4) = RevocationGuard::new();
+        assert!(restored.register(h4) > id3);
+    }
+
+    #[test]
+    fn truncated_snapshot_is_rejected() {
+        let registry = GrantRegistry::new();
+        let (handle, _guard) = RevocationGuard::new();
+        let id = registry.register(handle);
+        registry.revoke(id);
+        let snapshot = registry.dump();
+        let err = GrantRegistry::load(&snapshot[..snapshot.len() - 1])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("invalidSnapshot"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_in_flight_calls() {
+        let registry = GrantRegistry::new();
+        let (handle, guard) = RevocationGuard::new();
+        registry.register(handle);
+        let permit = registry.in_flight().enter();
+
+        let shutdown = registry.shutdown();
+        tokio::pin!(shutdown);
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => panic!("shutdown finished with a call in flight"),
+            _ = std::future::ready(()) => {}
+        }
+        // Revocation happens before draining.
+        assert!(guard.check().is_err());
+
+        drop(permit);
+        shutdown.await;
+        assert_eq!(registry.in_flight().count(), 0);
+    }
+}
@@ -3,10 +3,64 @@
 //! The searcher retains a [`RevocationHandle`] and can call [`revoke()`](RevocationHandle::revoke)
 //! at any time. The [`RevocationGuard`] is shared with capability servers and checked on every
 //! RPC call. Revocation is a one-way monotonic latch: once true, always true.
+//!
+//! Control over a long-lived grant can be handed off (e.g. to a different
+//! operator) with [`rotate()`](RevocationHandle::rotate): the returned handle
+//! becomes the only one able to revoke, and the previous one goes stale.
+//!
+//! A capability re-delegated to a sub-builder gets a guard from
+//! [`derive_child()`](RevocationGuard::derive_child): revoking any ancestor
+//! revokes it, while revoking the child leaves its ancestors untouched.
 
+use crate::audit::{record_audit, AuditEvent, AuditSink};
+use crate::error::MembraneError;
 use capnp::Error;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+type Listener = Box<dyn FnOnce() + Send>;
+
+/// Callbacks run once when the grant is revoked.
+type RevokeListeners = Arc<Listeners>;
+
+#[derive(Default)]
+struct Listeners {
+    pending: Mutex<Vec<(u64, Listener)>>,
+    next_id: AtomicU64,
+    /// For a derived grant: the entry cascading its parent's revocation
+    /// here, removed once the last guard and handle for it are dropped.
+    cascade: OnceLock<(Weak<Listeners>, u64)>,
+}
+
+impl Listeners {
+    fn remove(&self, id: u64) {
+        self.pending.lock().unwrap().retain(|(i, _)| *i != id);
+    }
+
+    /// Take and run every pending callback.
+    fn run(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for (_, f) in pending {
+            f();
+        }
+    }
+}
+
+impl Drop for Listeners {
+    fn drop(&mut self) {
+        if let Some((parent, id)) = self.cascade.get() {
+            if let Some(parent) = parent.upgrade() {
+                parent.remove(*id);
+            }
+        }
+    }
+}
+
+/// Why the grant was revoked; set once, by the first revocation.
+type RevokeReason = Arc<Mutex<Option<String>>>;
+
+/// Reason reported when revoked without one.
+const DEFAULT_REASON: &str = "bundle grant has been revoked";
 
 /// Guard that checks whether the bundle grant has been revoked.
 /// Shared between the searcher's revocation handle and all
@@ -14,47 +68,218 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct RevocationGuard {
     revoked: Arc<AtomicBool>,
+    reason: RevokeReason,
+    listeners: RevokeListeners,
+    /// Guard this one was derived from; its revocation cascades here.
+    parent: Option<Box<RevocationGuard>>,
 }
 
 /// Handle retained by the searcher to revoke the grant.
 /// Calling [`revoke()`](Self::revoke) is idempotent.
 pub struct RevocationHandle {
     revoked: Arc<AtomicBool>,
+    reason: RevokeReason,
+    listeners: RevokeListeners,
+    /// Generation of the currently authorized handle, shared by all handles.
+    current: Arc<Mutex<u64>>,
+    generation: u64,
+    audit: Option<Arc<dyn AuditSink>>,
+    parent: Option<Box<RevocationGuard>>,
 }
 
 impl RevocationGuard {
     /// Create a new revocation pair: handle (for the searcher) and guard (for capability servers).
     pub fn new() -> (RevocationHandle, Self) {
-        let flag = Arc::new(AtomicBool::new(false));
+        Self::from_shared(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Create a revocation pair over an existing flag, e.g. one restored
+    /// from persistent storage. A flag that is already set yields a pair in
+    /// the revoked state, reporting the default reason.
+    pub fn from_shared(flag: Arc<AtomicBool>) -> (RevocationHandle, Self) {
+        let reason: RevokeReason = Arc::default();
+        let listeners: RevokeListeners = Arc::default();
         let handle = RevocationHandle {
             revoked: flag.clone(),
+            reason: reason.clone(),
+            listeners: listeners.clone(),
+            current: Arc::new(Mutex::new(0)),
+            generation: 0,
+            audit: None,
+            parent: None,
+        };
+        let guard = RevocationGuard {
+            revoked: flag,
+            reason,
+            listeners,
+            parent: None,
         };
-        let guard = RevocationGuard { revoked: flag };
         (handle, guard)
     }
 
-    /// Check whether the grant has been revoked.
-    /// Returns `Ok(())` if still valid, `Err` if revoked.
+    /// Create a pair for a sub-grant of this one. The child guard fails if
+    /// either the child or any ancestor is revoked, and its `on_revoke`
+    /// listeners run on whichever comes first. The child handle revokes
+    /// only the child.
+    pub fn derive_child(&self) -> (RevocationHandle, Self) {
+        let (mut handle, mut guard) = Self::new();
+        handle.parent = Some(Box::new(self.clone()));
+        guard.parent = Some(Box::new(self.clone()));
+        let listeners = Arc::downgrade(&guard.listeners);
+        if let Some(id) = self.register(Box::new(move || {
+            if let Some(listeners) = listeners.upgrade() {
+                listeners.run();
+            }
+        })) {
+            let _ = guard
+                .listeners
+                .cascade
+                .set((Arc::downgrade(&self.listeners), id));
+        }
+        (handle, guard)
+    }
+
+    /// Run `f` once when the grant is revoked (immediately if it already is).
+    /// Used to release per-grant state such as result caches.
+    pub fn on_revoke(&self, f: impl FnOnce() + Send + 'static) {
+        self.register(Box::new(f));
+    }
+
+    /// Queue `f` and return its id, or run it now and return `None` if the
+    /// grant is already revoked.
+    fn register(&self, f: Listener) -> Option<u64> {
+        let mut pending = self.listeners.pending.lock().unwrap();
+        if self.check().is_err() {
+            drop(pending);
+            f();
+            return None;
+        }
+        let id = self.listeners.next_id.fetch_add(1, Ordering::Relaxed);
+        pending.push((id, f));
+        Some(id)
+    }
+
+    /// Check whether the grant, or any grant it was derived from, has been
+    /// revoked. Returns `Ok(())` if still valid, `Err` with
+    /// `revoked: <reason>` if revoked.
     pub fn check(&self) -> Result<(), Error> {
         if self.revoked.load(Ordering::Acquire) {
-            return Err(Error::failed(
-                "revoked: bundle grant has been revoked".to_string(),
-            ));
+            let reason = self.reason.lock().unwrap();
+            return Err(MembraneError::Revoked {
+                reason: reason.as_deref().unwrap_or(DEFAULT_REASON).to_string(),
+            }
+            .into());
+        }
+        match &self.parent {
+            Some(parent) => parent.check(),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
 
 impl RevocationHandle {
+    /// Record the first revocation to `sink`.
+    pub fn with_audit(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit = Some(sink);
+        self
+    }
+
     /// Revoke the grant. Idempotent — calling multiple times is safe.
+    ///
+    /// A stale (rotated-out) handle cannot revoke; the attempt is logged.
+    /// Use [`try_revoke()`](Self::try_revoke) to observe that case.
     pub fn revoke(&self) {
-        self.revoked.store(true, Ordering::Release);
+        if let Err(e) = self.try_revoke() {
+            tracing::warn!("revoke ignored: {}", e);
+        }
+    }
+
+    /// Revoke the grant, telling builders why (e.g. `"bundle landed
+    /// elsewhere"`). Only the first revocation's reason is kept.
+    ///
+    /// Like [`revoke()`](Self::revoke), a stale handle's attempt is logged.
+    pub fn revoke_with_reason(&self, reason: String) {
+        if let Err(e) = self.try_revoke_with_reason(reason) {
+            tracing::warn!("revoke ignored: {}", e);
+        }
+    }
+
+    /// Revoke the grant, failing if this handle has been rotated out.
+    pub fn try_revoke(&self) -> Result<(), Error> {
+        self.try_revoke_with_reason(DEFAULT_REASON.to_string())
+    }
+
+    /// [`try_revoke()`](Self::try_revoke) with a reason for builders.
+    pub fn try_revoke_with_reason(&self, reason: String) -> Result<(), Error> {
+        let current = self.current.lock().unwrap();
+        self.check_current(*current)?;
+        let was_revoked = {
+            // Set the reason before the flag so a guard never sees one
+            // without the other.
+            let mut stored = self.reason.lock().unwrap();
+            let was_revoked = self.revoked.load(Ordering::Acquire);
+            if !was_revoked {
+                *stored = Some(reason);
+                self.revoked.store(true, Ordering::Release);
+            }
+            was_revoked
+        };
+        if !was_revoked {
+            record_audit(&self.audit, AuditEvent::Revoke);
+            self.listeners.run();
+        }
+        Ok(())
+    }
+
+    /// Hand off revocation control: returns a new handle sharing the same
+    /// flag and makes this one stale. Revocation state is preserved.
+    pub fn rotate(&self) -> Result<RevocationHandle, Error> {
+        let mut current = self.current.lock().unwrap();
+        self.check_current(*current)?;
+        *current += 1;
+        Ok(RevocationHandle {
+            revoked: self.revoked.clone(),
+            reason: self.reason.clone(),
+            listeners: self.listeners.clone(),
+            current: self.current.clone(),
+            generation: *current,
+            audit: self.audit.clone(),
+            parent: self.parent.clone(),
+        })
+    }
+
+    /// Whether this handle is still the one authorized to revoke.
+    pub fn is_current(&self) -> bool {
+        *self.current.lock().unwrap() == self.generation
+    }
+
+    fn check_current(&self, current: u64) -> Result<(), Error> {
+        if current != self.generation {
+            return Err(MembraneError::StaleRevocationHandle.into());
+        }
+        Ok(())
     }
 
     /// Check whether revocation has been triggered.
     pub fn is_revoked(&self) -> bool {
         self.revoked.load(Ordering::Acquire)
     }
+
+    /// Why the grant was revoked; `None` if it is not, or was revoked
+    /// without a recorded reason.
+    pub fn reason(&self) -> Option<String> {
+        self.reason.lock().unwrap().clone()
+    }
+
+    /// A guard for this grant, for minting further capability servers.
+    pub fn guard(&self) -> RevocationGuard {
+        RevocationGuard {
+            revoked: self.revoked.clone(),
+            reason: self.reason.clone(),
+            listeners: self.listeners.clone(),
+            parent: self.parent.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -76,6 +301,94 @@ mod tests {
         assert!(res.unwrap_err().to_string().contains("revoked"));
     }
 
+    #[test]
+    fn guard_reports_revocation_reason() {
+        let (handle, guard) = RevocationGuard::new();
+        handle.revoke_with_reason("bundle landed elsewhere".to_string());
+        let err = guard.check().unwrap_err();
+        assert_eq!(err.extra, "revoked: bundle landed elsewhere");
+
+        // The first reason sticks.
+        handle.revoke_with_reason("changed my mind".to_string());
+        let err = guard.check().unwrap_err();
+        assert_eq!(err.extra, "revoked: bundle landed elsewhere");
+    }
+
+    #[test]
+    fn plain_revoke_uses_default_reason() {
+        let (handle, guard) = RevocationGuard::new();
+        handle.revoke();
+        let err = guard.check().unwrap_err();
+        assert_eq!(err.extra, "revoked: bundle grant has been revoked");
+    }
+
+    #[test]
+    fn shared_flag_set_elsewhere_is_revoked() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let (handle, guard) = RevocationGuard::from_shared(flag.clone());
+        assert!(guard.check().is_ok());
+        flag.store(true, Ordering::Release);
+        assert!(handle.is_revoked());
+        let err = guard.check().unwrap_err();
+        assert_eq!(err.extra, "revoked: bundle grant has been revoked");
+    }
+
+    #[test]
+    fn revoking_root_cascades_through_two_levels() {
+        let (root, root_guard) = RevocationGuard::new();
+        let (_child, child_guard) = root_guard.derive_child();
+        assert!(child_guard.check().is_ok());
+
+        root.revoke_with_reason("bundle landed".to_string());
+        let err = child_guard.check().unwrap_err();
+        assert_eq!(err.extra, "revoked: bundle landed");
+    }
+
+    #[test]
+    fn revoking_root_cascades_through_three_levels() {
+        let (root, root_guard) = RevocationGuard::new();
+        let (_mid, mid_guard) = root_guard.derive_child();
+        let (_leaf, leaf_guard) = mid_guard.derive_child();
+        let cleared = Arc::new(AtomicBool::new(false));
+        let flag = cleared.clone();
+        leaf_guard.on_revoke(move || flag.store(true, Ordering::SeqCst));
+
+        root.revoke();
+        assert!(mid_guard.check().is_err());
+        assert!(leaf_guard.check().is_err());
+        assert!(cleared.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dropped_child_unregisters_from_parent() {
+        let (root, root_guard) = RevocationGuard::new();
+        for _ in 0..16 {
+            let (child, child_guard) = root_guard.derive_child();
+            let _rotated = child.rotate().unwrap();
+            drop(child_guard.clone());
+        }
+        assert!(root_guard.listeners.pending.lock().unwrap().is_empty());
+
+        // A live child still cascades.
+        let (_child, child_guard) = root_guard.derive_child();
+        assert_eq!(root_guard.listeners.pending.lock().unwrap().len(), 1);
+        root.revoke();
+        assert!(child_guard.check().is_err());
+    }
+
+    #[test]
+    fn revoking_leaf_leaves_ancestors_valid() {
+        let (_root, root_guard) = RevocationGuard::new();
+        let (_mid, mid_guard) = root_guard.derive_child();
+        let (leaf, leaf_guard) = mid_guard.derive_child();
+
+        leaf.revoke();
+        assert!(leaf_guard.check().is_err());
+        assert!(leaf.guard().check().is_err());
+        assert!(mid_guard.check().is_ok());
+        assert!(root_guard.check().is_ok());
+    }
+
     #[test]
     fn revoke_is_idempotent() {
         let (handle, guard) = RevocationGuard::new();
@@ -92,4 +405,74 @@ mod tests {
         handle.revoke();
         assert!(guard2.check().is_err());
     }
+
+    #[test]
+    fn first_revoke_is_audited_once() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<AuditEvent>>);
+        impl AuditSink for Recorder {
+            fn record(&self, event: &AuditEvent) -> Result<(), Error> {
+                self.0.lock().unwrap().push(event.clone());
+                Ok(())
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let (handle, _guard) = RevocationGuard::new();
+        let handle = handle.with_audit(recorder.clone());
+        handle.revoke();
+        handle.revoke();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![AuditEvent::Revoke]);
+    }
+
+    #[test]
+    fn rotated_out_handle_cannot_revoke() {
+        let (old, guard) = RevocationGuard::new();
+        let new = old.rotate().unwrap();
+        assert!(!old.is_current());
+        assert!(new.is_current());
+
+        let err = old.try_revoke().unwrap_err();
+        assert!(err.to_string().contains("staleRevocationHandle"));
+        old.revoke(); // logged no-op
+        assert!(guard.check().is_ok());
+        assert!(old.rotate().is_err());
+
+        new.revoke();
+        assert!(guard.check().is_err());
+    }
+
+    #[test]
+    fn rotation_preserves_revoked_state() {
+        let (handle, guard) = RevocationGuard::new();
+        handle.revoke();
+        let rotated = handle.rotate().unwrap();
+        assert!(rotated.is_revoked());
+        assert!(guard.check().is_err());
+    }
+
+    #[test]
+    fn on_revoke_runs_once_on_revocation() {
+        use std::sync::atomic::AtomicUsize;
+
+        let (handle, guard) = RevocationGuard::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+        guard.on_revoke(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        handle.revoke();
+        handle.revoke();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Registering after revocation runs immediately.
+        let c = calls.clone();
+        guard.on_revoke(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
 }
@@ -0,0 +1,124 @@
+//! Just enough RLP to read fields out of a signed legacy transaction.
+//!
+//! Bundle txs are otherwise treated as opaque bytes; this exists for policy
+//! checks that need one field, not as a general decoder.
+
+use crate::error::MembraneError;
+use capnp::Error;
+
+fn invalid(detail: &str) -> Error {
+    MembraneError::InvalidTx {
+        detail: detail.to_string(),
+    }
+    .into()
+}
+
+/// Split the first RLP item off `buf`: `(is_list, payload, rest)`.
+fn split_item(buf: &[u8]) -> Result<(bool, &[u8], &[u8]), Error> {
+    let (&prefix, tail) = buf.split_first().ok_or_else(|| invalid("truncated RLP"))?;
+    let (is_list, header_len, payload_len) = match prefix {
+        0x00..=0x7f => return Ok((false, &buf[..1], tail)),
+        0x80..=0xb7 => (false, 1, usize::from(prefix - 0x80)),
+        0xc0..=0xf7 => (true, 1, usize::from(prefix - 0xc0)),
+        _ => {
+            let (is_list, len_of_len) = if prefix <= 0xbf {
+                (false, usize::from(prefix - 0xb7))
+            } else {
+                (true, usize::from(prefix - 0xf7))
+            };
+            let len_bytes = tail
+                .get(..len_of_len)
+                .ok_or_else(|| invalid("truncated RLP length"))?;
+            if len_of_len > std::mem::size_of::<usize>() {
+                return Err(invalid("RLP length overflow"));
+            }
+            let len = len_bytes
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | usize::from(b));
+            (is_list, 1 + len_of_len, len)
+        }
+    };
+    let end = header_len
+        .checked_add(payload_len)
+        .filter(|&end| end <= buf.len())
+        .ok_or_else(|| invalid("truncated RLP payload"))?;
+    Ok((is_list, &buf[header_len..end], &buf[end..]))
+}
+
+/// Big-endian unsigned integer from an RLP string payload.
+fn to_u64(payload: &[u8]) -> Result<u64, Error> {
+    if payload.len() > 8 {
+        return Err(invalid("integer too large"));
+    }
+    Ok(payload
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 8) | u64::from(b)))
+}
+
+/// The signature `v` of a legacy tx: `[nonce, gasPrice, gas, to, value,
+/// data, v, r, s]`.
+pub(crate) fn legacy_v(tx: &[u8]) -> Result<u64, Error> {
+    let (is_list, mut fields, _) = split_item(tx)?;
+    if !is_list {
+        return Err(invalid("legacy tx is not an RLP list"));
+    }
+    for _ in 0..6 {
+        fields = split_item(fields)?.2;
+    }
+    let (is_list, v, _) = split_item(fields)?;
+    if is_list {
+        return Err(invalid("legacy tx v is a list"));
+    }
+    to_u64(v)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// RLP-encode a legacy tx with the given `v` and placeholder fields.
+    pub(crate) fn legacy_tx(v: u64) -> Vec<u8> {
+        let mut payload = vec![
+            0x80, // nonce 0
+            0x01, // gasPrice 1
+            0x82, 0x52, 0x08, // gas 21000
+            0x94, // to: 20 bytes
+        ];
+        payload.extend_from_slice(&[0x11; 20]);
+        payload.extend_from_slice(&[0x80, 0x80]); // value 0, empty data
+        let v_bytes: Vec<u8> = v
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        match v_bytes.as_slice() {
+            [b] if *b < 0x80 => payload.push(*b),
+            bytes => {
+                payload.push(0x80 + bytes.len() as u8);
+                payload.extend_from_slice(bytes);
+            }
+        }
+        for _ in 0..2 {
+            payload.push(0xa0); // r, s: 32 bytes each
+            payload.extend_from_slice(&[0x22; 32]);
+        }
+        let mut tx = vec![0xf8, payload.len() as u8];
+        tx.extend_from_slice(&payload);
+        tx
+    }
+
+    #[test]
+    fn reads_v_from_legacy_tx() {
+        assert_eq!(legacy_v(&legacy_tx(27)).unwrap(), 27);
+        // EIP-155 on mainnet: chain_id * 2 + 35.
+        assert_eq!(legacy_v(&legacy_tx(37)).unwrap(), 37);
+        assert_eq!(legacy_v(&legacy_tx(2 * 11155111 + 36)).unwrap(), 22310258);
+    }
+
+    #[test]
+    fn truncated_tx_is_invalid() {
+        let tx = legacy_tx(27);
+        let err = legacy_v(&tx[..tx.len() - 40]).unwrap_err();
+        assert!(err.to_string().contains("invalidTx"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Simulation backends that ship with the crate.
+//!
+//! [`DryRunSimulator`] answers every simulation with a canned result
+//! without touching a node. Pass it as the grant's simulator to hand out a
+//! grant for integration testing or builder onboarding: the full guard
+//! stack and capnp wiring still run, only the backend is stubbed.
+//!
+//! ```ignore
+//! let sim = Arc::new(DryRunSimulator::default());
+//! let (handle, bundle, client) = bundle_membrane(epoch_rx, bundle, 100, 110, pubkey, sim);
+//! ```
+
+use crate::access::{BundleSimulator, BundleSpec, SimResult, SimulatorInfo};
+use capnp::Error;
+use std::sync::{Arc, Mutex};
+
+/// Backend that returns a configurable canned [`SimResult`].
+///
+/// Clones share the canned result, so a handle kept by the operator can
+/// change what grants already issued with it return.
+#[derive(Clone)]
+pub struct DryRunSimulator {
+    result: Arc<Mutex<SimResult>>,
+}
+
+impl DryRunSimulator {
+    /// Answer every simulation with `result`.
+    pub fn new(result: SimResult) -> Self {
+        Self {
+            result: Arc::new(Mutex::new(result)),
+        }
+    }
+
+    /// Replace the canned result for subsequent simulations.
+    pub fn set_result(&self, result: SimResult) {
+        *self.result.lock().unwrap() = result;
+    }
+}
+
+impl Default for DryRunSimulator {
+    /// A successful simulation using 21000 gas.
+    fn default() -> Self {
+        Self::new(SimResult {
+            gas_used: 21_000,
+            success: true,
+            ..Default::default()
+        })
+    }
+}
+
+impl BundleSimulator for DryRunSimulator {
+    fn simulate(
+        &self,
+        _bundle: &BundleSpec,
+        _target_block: u64,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimResult, Error>> + Send>> {
+        let result = self.result.lock().unwrap().clone();
+        Box::pin(async move { Ok(result) })
+    }
+
+    fn info(&self) -> SimulatorInfo {
+        SimulatorInfo {
+            kind: "dryRun".to_string(),
+            ..SimulatorInfo::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle_capnp;
+    use crate::grant::bundle_membrane;
+    use crate::pubkey::BuilderKey;
+    use k256::ecdsa::SigningKey;
+    use membrane_core::epoch::Epoch;
+    use tokio::sync::watch;
+
+    fn bundle() -> BundleSpec {
+        BundleSpec {
+            txs: vec![vec![0x01]],
+        }
+    }
+
+    #[tokio::test]
+    async fn canned_result_is_configurable() {
+        let sim = DryRunSimulator::default();
+        let first = sim.simulate(&bundle(), 105).await.unwrap();
+        assert!(first.success);
+        assert_eq!(first.gas_used, 21_000);
+
+        let operator = sim.clone();
+        operator.set_result(SimResult {
+            revert_reason: "canned revert".to_string(),
+            ..Default::default()
+        });
+        let second = sim.simulate(&bundle(), 105).await.unwrap();
+        assert!(!second.success);
+        assert_eq!(second.revert_reason, "canned revert");
+    }
+
+    #[tokio::test]
+    async fn dry_run_grant_still_runs_guards() {
+        let (_tx, rx) = watch::channel(Epoch {
+            seq: 1,
+            head: vec![],
+            adopted_block: 100,
+        });
+        let key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let (handle, _bundle, membrane) = bundle_membrane(
+            rx,
+            bundle(),
+            100,
+            110,
+            BuilderKey::PublicKey(k256::PublicKey::from(key.verifying_key())).to_bytes(),
+            Arc::new(DryRunSimulator::default()),
+        );
+        let resp = membrane.graft_request().send().promise.await.unwrap();
+        let access: bundle_capnp::bundle_access::Client = resp
+            .get()
+            .unwrap()
+            .get_session()
+            .unwrap()
+            .get_extension()
+            .unwrap()
+            .get_bundle_access()
+            .unwrap();
+
+        let mut req = access.simulate_request();
+        req.get().set_target_block(105);
+        let resp = req.send().promise.await.unwrap();
+        let result = resp.get().unwrap().get_result().unwrap();
+        assert!(result.get_success());
+        assert_eq!(
+            result.get_simulated_by_backend().unwrap().to_str().unwrap(),
+            "dryRun"
+        );
+
+        let mut req = access.simulate_request();
+        req.get().set_target_block(200);
+        assert!(req.send().promise.await.is_err());
+
+        handle.revoke();
+        let mut req = access.include_request();
+        req.get().set_target_block(105);
+        let err = req.send().promise.await.err().unwrap();
+        assert!(err.to_string().contains("revoked"));
+    }
+}
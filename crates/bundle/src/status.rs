@@ -0,0 +1,131 @@
+//! Client-side epoch tracking for builders.
+//!
+//! A session's `statusPoller` fails once the epoch it was issued under is
+//! no longer current. [`watch_epoch_status`] polls it in the background and
+//! exposes the outcome as a `watch` flag, so a builder can stop simulating
+//! as soon as its grant goes stale instead of discovering it through a
+//! `staleEpoch` error on a live call.
+
+use membrane_core::stem_capnp;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Poll `poller` every `interval` and report whether the session's epoch is
+/// still current.
+///
+/// The flag starts `true` and flips to `false` on the first failed poll.
+/// Any failure counts, a dropped connection included: either way the
+/// session can no longer be used. An epoch never becomes current again, so
+/// polling stops there, and also once every receiver has been dropped.
+///
+/// capnp clients are not `Send`; this spawns with
+/// [`tokio::task::spawn_local`] and must be called inside a `LocalSet`.
+pub fn watch_epoch_status(
+    poller: stem_capnp::status_poller::Client,
+    interval: Duration,
+) -> watch::Receiver<bool> {
+    let (tx, rx) = watch::channel(true);
+    tokio::task::spawn_local(async move {
+        loop {
+            if tx.is_closed() {
+                return;
+            }
+            if poller.poll_status_request().send().promise.await.is_err() {
+                let _ = tx.send(false);
+                return;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::BundleSpec;
+    use crate::bundle_capnp;
+    use crate::grant::bundle_membrane;
+    use crate::pubkey::BuilderKey;
+    use crate::simulator::DryRunSimulator;
+    use k256::ecdsa::SigningKey;
+    use membrane_core::epoch::Epoch;
+    use std::sync::Arc;
+
+    fn epoch(seq: u64) -> Epoch {
+        Epoch {
+            seq,
+            head: vec![],
+            adopted_block: 100,
+        }
+    }
+
+    async fn session_poller(
+        membrane: &stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned>,
+    ) -> stem_capnp::status_poller::Client {
+        let resp = membrane.graft_request().send().promise.await.unwrap();
+        resp.get()
+            .unwrap()
+            .get_session()
+            .unwrap()
+            .get_status_poller()
+            .unwrap()
+    }
+
+    fn membrane(
+        rx: watch::Receiver<Epoch>,
+    ) -> stem_capnp::membrane::Client<bundle_capnp::bundle_grant::Owned> {
+        let key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let (_handle, _bundle, membrane) = bundle_membrane(
+            rx,
+            BundleSpec {
+                txs: vec![vec![0x01]],
+            },
+            100,
+            110,
+            BuilderKey::PublicKey(k256::PublicKey::from(key.verifying_key())).to_bytes(),
+            Arc::new(DryRunSimulator::default()),
+        );
+        membrane
+    }
+
+    #[tokio::test]
+    async fn flag_flips_when_epoch_advances() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let (tx, rx) = watch::channel(epoch(1));
+                let poller = session_poller(&membrane(rx)).await;
+                let mut current = watch_epoch_status(poller, Duration::from_millis(5));
+
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                assert!(*current.borrow());
+
+                tx.send(epoch(2)).unwrap();
+                tokio::time::timeout(Duration::from_secs(1), current.changed())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert!(!*current.borrow());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn session_issued_after_advance_is_current() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let (tx, rx) = watch::channel(epoch(1));
+                let membrane = membrane(rx);
+                let stale =
+                    watch_epoch_status(session_poller(&membrane).await, Duration::from_millis(5));
+                tx.send(epoch(2)).unwrap();
+                let fresh =
+                    watch_epoch_status(session_poller(&membrane).await, Duration::from_millis(5));
+
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                assert!(!*stale.borrow());
+                assert!(*fresh.borrow());
+            })
+            .await;
+    }
+}
@@ -0,0 +1,85 @@
+//! Mapping between epochs and chain block height.
+//!
+//! An [`Epoch`] records the block at which it was adopted, but the chain tip
+//! keeps moving while the epoch stays current. [`EpochChainMap`] is the single
+//! place that answers "what is the head block right now?" for a given epoch.
+
+use crate::epoch::Epoch;
+use tokio::sync::watch;
+
+/// Resolves the current chain head for an epoch.
+pub trait EpochChainMap: Send + Sync + 'static {
+    /// Current head block while `epoch` is the adopted epoch.
+    fn head_block(&self, epoch: &Epoch) -> u64;
+
+    /// Hash of the block `head_block` reports, for maps that track it.
+    /// Defaults to `None`.
+    fn head_hash(&self, _epoch: &Epoch) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Number of blocks the chain has advanced since `epoch` was adopted.
+    fn epoch_age(&self, epoch: &Epoch) -> u64 {
+        self.head_block(epoch).saturating_sub(epoch.adopted_block)
+    }
+}
+
+/// Default mapping: the head is the epoch's `adopted_block`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AdoptedBlockMap;
+
+impl EpochChainMap for AdoptedBlockMap {
+    fn head_block(&self, epoch: &Epoch) -> u64 {
+        epoch.adopted_block
+    }
+}
+
+/// Mapping backed by a live chain-tip feed (e.g. polled `eth_blockNumber`).
+///
+/// Never reports a head behind the epoch's `adopted_block`, so a lagging
+/// feed cannot make an epoch look older than it is.
+#[derive(Clone)]
+pub struct ChainTipMap {
+    pub tip: watch::Receiver<u64>,
+}
+
+impl EpochChainMap for ChainTipMap {
+    fn head_block(&self, epoch: &Epoch) -> u64 {
+        (*self.tip.borrow()).max(epoch.adopted_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn epoch(adopted_block: u64) -> Epoch {
+        Epoch {
+            seq: 1,
+            head: vec![],
+            adopted_block,
+        }
+    }
+
+    #[test]
+    fn adopted_block_map_uses_adopted_block() {
+        let map = AdoptedBlockMap;
+        assert_eq!(map.head_block(&epoch(100)), 100);
+        assert_eq!(map.epoch_age(&epoch(100)), 0);
+    }
+
+    #[test]
+    fn chain_tip_map_follows_tip() {
+        let (tx, rx) = watch::channel(105);
+        let map = ChainTipMap { tip: rx };
+        assert_eq!(map.head_block(&epoch(100)), 105);
+        assert_eq!(map.epoch_age(&epoch(100)), 5);
+
+        tx.send(112).unwrap();
+        assert_eq!(map.head_block(&epoch(100)), 112);
+
+        // A tip behind the adoption block is clamped up.
+        tx.send(90).unwrap();
+        assert_eq!(map.head_block(&epoch(100)), 100);
+    }
+}
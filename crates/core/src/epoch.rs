@@ -34,6 +34,13 @@ pub struct EpochGuard {
 }
 
 impl EpochGuard {
+    /// Fail with `staleEpoch` unless the issuing epoch is still current.
+    ///
+    /// A watch channel keeps only its latest value, so however far the
+    /// sender has run ahead, `borrow` sees the newest epoch and never an
+    /// intermediate one. `borrow_and_update` would give the same answer; it
+    /// only additionally marks the value seen for `changed()`, which the
+    /// guard doesn't use.
     pub fn check(&self) -> Result<(), Error> {
         let current = self.receiver.borrow();
         if current.seq != self.issued_seq {
@@ -80,4 +87,22 @@ mod tests {
         assert!(res.is_err());
         assert!(res.unwrap_err().to_string().contains("staleEpoch"));
     }
+
+    #[tokio::test]
+    async fn epoch_guard_sees_latest_epoch_after_many_updates() {
+        let (tx, rx) = watch::channel(epoch(1, b"head1", 100));
+        let current = EpochGuard {
+            issued_seq: 50,
+            receiver: rx.clone(),
+        };
+        let intermediate = EpochGuard {
+            issued_seq: 49,
+            receiver: rx,
+        };
+        for seq in 2..=50 {
+            tx.send(epoch(seq, b"head", 100 + seq)).unwrap();
+        }
+        assert!(current.check().is_ok());
+        assert!(intermediate.check().is_err());
+    }
 }
@@ -7,15 +7,18 @@
 //! - **EpochGuard** — checks whether a capability's epoch is still current
 //! - **MembraneServer** — generic server that issues epoch-scoped sessions via `graft()`
 //! - **SessionExtensionBuilder** — trait for injecting platform-specific capabilities into sessions
+//! - **EpochChainMap** — resolves the current chain head block for an epoch
 
 #[allow(unused_parens)]
 pub mod stem_capnp {
     include!(concat!(env!("OUT_DIR"), "/capnp/stem_capnp.rs"));
 }
 
+pub mod chain;
 pub mod epoch;
 pub mod membrane;
 
+pub use chain::{AdoptedBlockMap, ChainTipMap, EpochChainMap};
 pub use epoch::{Epoch, EpochGuard, fill_epoch_builder};
 pub use membrane::{
     membrane_client, MembraneServer, NoExtension, SessionExtensionBuilder, StatusPollerServer,
@@ -21,6 +21,19 @@ where
         guard: &EpochGuard,
         builder: <SessionExt as capnp::traits::Owned>::Builder<'_>,
     ) -> Result<(), Error>;
+
+    /// Authenticate the grafting peer before the session is built.
+    ///
+    /// `signer` is the capability passed to `graft`, if any. The graft fails
+    /// with the returned error and `build` is never called. Defaults to
+    /// accepting every peer.
+    fn authenticate(
+        &self,
+        _signer: Option<stem_capnp::signer::Client>,
+        _epoch: &Epoch,
+    ) -> Promise<(), Error> {
+        Promise::ok(())
+    }
 }
 
 /// No-op extension builder for sessions without platform-specific capabilities.
@@ -76,34 +89,43 @@ where
 {
     fn graft(
         self: capnp::capability::Rc<Self>,
-        _params: stem_capnp::membrane::GraftParams<SessionExt>,
+        params: stem_capnp::membrane::GraftParams<SessionExt>,
         mut results: stem_capnp::membrane::GraftResults<SessionExt>,
     ) -> Promise<(), Error> {
         let epoch = self.get_current_epoch();
-        let mut session_builder = results.get().init_session();
-        if fill_epoch_builder(&mut session_builder.reborrow().init_issued_epoch(), &epoch).is_err()
-        {
-            return Promise::err(Error::failed("fill issued epoch".to_string()));
-        }
-        let guard = EpochGuard {
-            issued_seq: epoch.seq,
-            receiver: self.receiver.clone(),
-        };
-        let poller = StatusPollerServer {
-            guard: guard.clone(),
-        };
-        session_builder
-            .reborrow()
-            .set_status_poller(new_client(poller));
+        let signer = params.get().and_then(|p| p.get_signer()).ok();
+        let auth = self.ext_builder.authenticate(signer, &epoch);
 
-        if let Err(e) = self
-            .ext_builder
-            .build(&guard, session_builder.reborrow().init_extension())
-        {
-            return Promise::err(e);
-        }
+        Promise::from_future(async move {
+            auth.await?;
+            // The peer authenticated against `epoch`; don't issue a session
+            // under one it never saw.
+            if self.get_current_epoch().seq != epoch.seq {
+                return Err(Error::failed(
+                    "staleEpoch: epoch advanced during graft".to_string(),
+                ));
+            }
 
-        Promise::ok(())
+            let mut session_builder = results.get().init_session();
+            if fill_epoch_builder(&mut session_builder.reborrow().init_issued_epoch(), &epoch)
+                .is_err()
+            {
+                return Err(Error::failed("fill issued epoch".to_string()));
+            }
+            let guard = EpochGuard {
+                issued_seq: epoch.seq,
+                receiver: self.receiver.clone(),
+            };
+            let poller = StatusPollerServer {
+                guard: guard.clone(),
+            };
+            session_builder
+                .reborrow()
+                .set_status_poller(new_client(poller));
+
+            self.ext_builder
+                .build(&guard, session_builder.reborrow().init_extension())
+        })
     }
 }
 